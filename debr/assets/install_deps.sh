@@ -1,3 +1,3 @@
 #!/bin/bash
 
-apt install -y live-build snapd pkg-config
\ No newline at end of file
+apt install -y live-build squashfs-tools ostree unzip pkg-config
\ No newline at end of file
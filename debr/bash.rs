@@ -8,11 +8,21 @@ use std::fs;
 use colored::*;
 
 pub fn cmd<I, S>(executable: S, args: I, working_dir: Option<&Path>) -> io::Result<()>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    cmd_env(executable, args, working_dir, &[])
+}
+
+// with additional environment variables for the child only
+pub fn cmd_env<I, S>(executable: S, args: I, working_dir: Option<&Path>, envs: &[(&str, String)]) -> io::Result<()>
 where
     I: IntoIterator<Item = S>,
     S: AsRef<OsStr>,
 {
     let mut command = Command::new(executable);
+    command.envs(envs.iter().cloned());
     if let Some(dir) = working_dir {
         if !dir.exists() {
             println!("Directory does not exist. Creating directory: {}", dir.display());
@@ -43,8 +53,7 @@ where
 
     if !status.success() {
         let code = status.code().unwrap_or(-1);
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
+        return Err(io::Error::other(
             format!("Command execution failed with status code: {}", code),
         ));
     }
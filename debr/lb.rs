@@ -1,15 +1,19 @@
 use std::io;
 use std::path::Path;
-use std::fs::remove_dir_all;
+use std::fs::{remove_dir_all, remove_file};
 use std::env::current_dir;
 use crate::bash;
 
 pub fn lb(args: &[&str], working_dir: Option<&Path>) -> io::Result<()> {
-    bash::cmd("lb", args.iter().copied(), working_dir)
+    lb_env(args, working_dir, &[])
 }
 
-pub fn build(working_dir: Option<&Path>) -> io::Result<()> {
-    lb(&["build"], working_dir)
+pub fn lb_env(args: &[&str], working_dir: Option<&Path>, envs: &[(&str, String)]) -> io::Result<()> {
+    bash::cmd_env("lb", args.iter().copied(), working_dir, envs)
+}
+
+pub fn build(working_dir: Option<&Path>, envs: &[(&str, String)]) -> io::Result<()> {
+    lb_env(&["build"], working_dir, envs)
 }
 
 pub fn clean(working_dir: Option<&Path>, build: Option<bool>) -> io::Result<()> {
@@ -23,6 +27,7 @@ pub fn clean(working_dir: Option<&Path>, build: Option<bool>) -> io::Result<()>
             current_dir()?
         };
         remove_dir_all(config_dir.join("config/")).ok();
+        remove_file(config_dir.join(".debr-manifest")).ok();
         Ok(())
     } else {
         Ok(())
@@ -14,7 +14,11 @@ pub struct Args {
     #[arg(short = 'c', long = "config", default_value_t = String::from("config.json"), help = "Path to the configuration file")]
     config: String,
     #[arg(short = 'o', long = "out-dir", default_value_t = String::from("out"), help = "Path for the live-debian-build to use")]
-    out_dir: String
+    out_dir: String,
+    #[arg(long = "strict", global = true, help = "Fail on lint findings instead of warning")]
+    strict: bool,
+    #[arg(long = "offline", global = true, help = "Use only what `debr fetch` cached, fail on anything missing")]
+    offline: bool
 }
 
 #[derive(Subcommand, Debug)]
@@ -23,20 +27,38 @@ pub enum Commands {
     Deps,
     #[command(about = "Initialize build")]
     Config,
+    #[command(about = "Download everything the config needs for `--offline`")]
+    Fetch {
+        #[arg(long = "packages", help = "Also mirror the apt packages of the build")]
+        packages: bool,
+    },
     
     #[command(about = "Build live debian")]
     Build,
+    #[command(about = "Show how the live-build config changed since `debr config` generated it")]
+    Diff,
     #[command(about = "Drop-in replacement for the lb command")]
     Lb {
         #[arg(help = "drop-in replacement for lb command")]
         lb_args: Option<Vec<String>>,
     },
+    #[command(about = "Inspect the live-build config", name = "lb-config")]
+    LbConfig {
+        #[command(subcommand)]
+        action: LbConfigAction,
+    },
     #[command(about = "Clean all live-build files except of cache, including the config")]
     Clean,
     #[command(about = "Clean all build files except of cache", name="clean-build")]
     CleanBuild,
 }
 
+#[derive(Subcommand, Debug)]
+pub enum LbConfigAction {
+    #[command(about = "Print the effective live-build settings, including defaults")]
+    Show,
+}
+
 fn main() {
     if let Err(e) = run() {
         eprintln!("Error: {}", e);
@@ -61,6 +83,18 @@ fn run() -> Result<(), Box<dyn Error>> {
             post_cfg::apply(&args, live_dir)?;
         }
 
+        Some(Commands::Fetch { packages }) => {
+            post_cfg::fetch(&args, live_dir, packages)?;
+            println!("Fetched everything for `debr --offline config`{}", if packages { " and `debr --offline build`" } else { "" });
+        }
+
+        Some(Commands::LbConfig { action: LbConfigAction::Show }) => {
+            if !live_dir.join("config/").exists() {
+                return Err(format!("No live-build config in {}, run `debr config` first", live_dir.display()).into());
+            }
+            print!("{}", post_cfg::lb_config::LbConfig::load(live_dir)?.show());
+        }
+
         Some(Commands::Clean) => {
             lb::clean(Some(live_dir), None)?;
         }
@@ -69,12 +103,30 @@ fn run() -> Result<(), Box<dyn Error>> {
             lb::clean(Some(live_dir),Some(true))?;
         }
 
+        Some(Commands::Diff) => {
+            let drift = post_cfg::drift::detect(&args, live_dir)?;
+            post_cfg::drift::print(&drift);
+            if drift.iter().all(|d| d.is_kept()) {
+                println!("No drift, {} matches {}", live_dir.join("config").display(), args.config);
+            }
+        }
+
         Some(Commands::Build) => {
             if !live_dir.join("config/").exists(){
                 println!("Using Default config");
                 post_cfg::apply(&args, live_dir)?;
+            } else if Path::new(&args.config).exists() {
+                let drift = post_cfg::drift::detect(&args, live_dir)?;
+                if drift.iter().any(|d| !d.is_kept()) {
+                    post_cfg::drift::print(&drift);
+                    if args.strict {
+                        return Err(format!("{} drifted from {}, run `debr config` or build without --strict", live_dir.join("config").display(), args.config).into());
+                    }
+                    eprintln!("W: {} drifted from {}, building anyway", live_dir.join("config").display(), args.config);
+                }
             }
-            lb::build(Some(live_dir))?;
+            let lb_env = post_cfg::prepare_build(&args, live_dir)?;
+            lb::build(Some(live_dir), &lb_env)?;
         }
 
         Some(Commands::Lb { lb_args }) => {
@@ -101,7 +153,7 @@ fn preprocess(args: &mut Vec<String>) {
                 args.insert(i, "--".to_string());
                 i += 1;
             }
-        } else if vec!["lb"].contains(&args[i].as_str()) {
+        } else if ["lb"].contains(&args[i].as_str()) {
             lb_found = true;
         }
         i += 1;
@@ -1,17 +1,36 @@
 use std::env;
-use std::fs::{copy, create_dir_all, set_permissions, write};
+use std::fs::{copy, create_dir_all, remove_dir_all, set_permissions, write};
 use std::io::{Error, ErrorKind};
-use std::path::Path;
-use std::collections::{HashMap, HashSet};
-use ordermap::OrderSet;
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, HashSet};
+use ordermap::{OrderMap, OrderSet};
 use std::os::unix::fs::PermissionsExt;
 
 mod cfg_parser;
+pub mod lb_config;
+pub mod drift;
+mod staging;
+mod snapshot;
+mod mirrors;
+mod apt_mirror;
+mod cross;
 mod json_cfg;
 mod sign;
 mod deboot_opt;
 mod hooks;
 mod snap;
+mod store;
+mod flatpak;
+mod downloads;
+mod tools;
+mod dconf;
+mod desktop;
+mod xdg;
+mod policies;
+mod debconf;
+mod lint;
+mod packages;
+mod package_lists;
 use crate::lb;
 
 use sign::place_key;
@@ -20,24 +39,95 @@ use crate::Args;
 
 pub fn s(_s: &str) -> String {_s.to_string()}
 
+// generates the config into a staging directory and swaps it in on success only
 pub fn apply(args: &Args, live_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    staging::recover(live_dir)?;
+    let staging_dir = Path::new(&args.out_dir).join("staging/live/");
+    remove_dir_all(&staging_dir).ok();
+    create_dir_all(&staging_dir)?;
+
+    let result = generate(args, &staging_dir).and_then(|generated| {
+        let variables = lb_config::LbConfig::load(&staging_dir)?.values();
+        let manifest = staging::Manifest { inputs: generated.inputs, variables, ..Default::default() };
+        staging::swap(&staging_dir, live_dir, manifest)?;
+        // same config, same mtimes, the kept files included
+        if let Some(epoch) = generated.source_date_epoch {
+            staging::set_mtimes(&live_dir.join("config"), epoch)?;
+        }
+        Ok(())
+    });
+    remove_dir_all(staging_dir.parent().unwrap()).ok();
+    result
+}
+
+// fills the caches `--offline` relies on, by generating the config into `<out_dir>/fetch/live/`
+pub fn fetch(args: &Args, live_dir: &Path, mirror_packages: bool) -> Result<(), Box<dyn std::error::Error>> {
+    if args.offline {
+        return Err("`debr fetch` needs network access, run it without --offline".into());
+    }
+    let fetch_dir = Path::new(&args.out_dir).join("fetch/live/");
+    remove_dir_all(&fetch_dir).ok();
+    create_dir_all(&fetch_dir)?;
+
+    let result = generate(args, &fetch_dir).and_then(|generated| {
+        if mirror_packages {
+            let lb_config = lb_config::LbConfig::load(&fetch_dir)?;
+            apt_mirror::fetch(&lb_config, &generated.packages, live_dir, &apt_mirror::dir(&args.out_dir))?;
+        }
+        Ok(())
+    });
+    remove_dir_all(fetch_dir.parent().unwrap()).ok();
+    result
+}
+
+// checks the emulation of cross builds and serves the apt mirror of `debr fetch --packages` if the config builds from it,
+// which `--offline` requires, returns the environment for `lb build`
+pub fn prepare_build(args: &Args, live_dir: &Path) -> Result<Vec<(&'static str, String)>, Box<dyn std::error::Error>> {
+    let lb_config = lb_config::LbConfig::load(live_dir)?;
+    cross::check(&lb_config)?;
+    let mut lb_env = Vec::new();
+    if let Some(port) = apt_mirror::port(&lb_config) {
+        apt_mirror::serve(&apt_mirror::dir(&args.out_dir), port)?;
+        // debootstrap would send the requests for the mirror to the proxy
+        let no_proxy = match env::var("no_proxy") {
+            Ok(no_proxy) if !no_proxy.is_empty() => format!("{},127.0.0.1", no_proxy),
+            _ => s("127.0.0.1"),
+        };
+        lb_env.push(("no_proxy", no_proxy.clone()));
+        lb_env.push(("NO_PROXY", no_proxy));
+    } else if args.offline {
+        return Err(format!(
+            "{} builds from the network, run `debr fetch --packages` and `debr --offline config` first",
+            live_dir.join("config").display()
+        ).into());
+    }
+    Ok(lb_env)
+}
+
+// what `generate` returns besides the config tree
+struct Generated {
+    // packages installed from the distribution
+    packages: OrderSet<String>,
+    inputs: String,
+    source_date_epoch: Option<u64>,
+}
+
+fn generate(args: &Args, live_dir: &Path) -> Result<Generated, Box<dyn std::error::Error>> {
     // commonly used paths
     let executable_path = env::current_exe()?;
     let dir = executable_path.parent().unwrap();
-    let bootstrap = live_dir.join("config/bootstrap");
-    let common = live_dir.join("config/common");
     let includes_after_packages = live_dir.join("config/includes.chroot_after_packages/");
 
     // parsed values
-    let mut includes_parsed: HashSet<String> = HashSet::new();
-    let mut purge_parsed: HashSet<String> = HashSet::new();
-    let mut includes_from_hook_parsed: HashSet<String> = HashSet::new();
-    let mut snaps_parsed: OrderSet<String> = OrderSet::new();
-    snaps_parsed.insert(s("snapd"));
+    let mut includes_parsed: OrderSet<String> = OrderSet::new();
+    let mut purge_parsed: OrderSet<String> = OrderSet::new();
+    let mut includes_from_hook_parsed: OrderSet<String> = OrderSet::new();
+    let mut snaps_parsed: OrderMap<String, json_cfg::Snap> = OrderMap::new();
     let mut extras_parsed : Vec<json_cfg::Extra> = Vec::new();
-    let mut e_service_parsed: HashSet<String> = HashSet::new();
-    let mut d_service_parsed: HashSet<String> = HashSet::new();
-    let mut keyrings_parsed: HashMap<String, String> = HashMap::new();
+    let mut e_service_parsed: OrderSet<String> = OrderSet::new();
+    let mut d_service_parsed: OrderSet<String> = OrderSet::new();
+    let mut keyrings_parsed: BTreeMap<String, String> = BTreeMap::new();
+    let mut sources_parsed: Vec<packages::Source> = Vec::new();
     
 
     // read config
@@ -45,35 +135,83 @@ pub fn apply(args: &Args, live_dir: &Path) -> Result<(), Box<dyn std::error::Err
     if !Path::new(config_path).exists() {
         return Err(Box::new(Error::new(ErrorKind::NotFound, format!("Configuration file '{}' does not exist", config_path.display()))));
     }
-    let config: json_cfg::Config = json_cfg::read_config(&config_path)?;
+    let config: json_cfg::Config = json_cfg::read_config(config_path)?;
+    let inputs = drift::fingerprint(&config, args)?;
+    if args.offline && config.extras.as_ref().is_some_and(|extras| !extras.is_empty()) {
+        return Err("`extras` are installed from their repositories during the build, which isn't possible --offline".into());
+    }
+    if args.offline {
+        let remote = config.tool_packages.as_ref().map(tools::remote).unwrap_or_default();
+        if !remote.is_empty() {
+            return Err(format!(
+                "`toolPackages` of {} are installed from their registries during the build, which isn't possible --offline, set a local `indexUrl`",
+                remote.join(", ")
+            ).into());
+        }
+    }
 
     
+    // reproducible builds, live-build and the tools it runs honour SOURCE_DATE_EPOCH, `lb build` reads it from config/common
+    let source_date_epoch = match config.source_date_epoch {
+        Some(epoch) => Some(epoch),
+        None => env::var("SOURCE_DATE_EPOCH").ok()
+            .map(|epoch| epoch.parse::<u64>().map_err(|_| format!("Invalid SOURCE_DATE_EPOCH `{}`", epoch)))
+            .transpose()?,
+    };
+    let lb_env: Vec<(&str, String)> = source_date_epoch.iter().map(|epoch| ("SOURCE_DATE_EPOCH", epoch.to_string())).collect();
+
     // lb config
     let dist = &config.dist.unwrap_or(s("bookworm"));
-    lb::lb(&["config","--distribution", dist], Some(live_dir))?;
+    lb::lb_env(&["config","--distribution", dist], Some(live_dir), &lb_env)?;
+    let mut lb_config = lb_config::LbConfig::load(live_dir)?;
 
     // architecture & apt archive areas
     let arch = &config.arch.unwrap_or(s("amd64"));
     let archive_areas = &config.archive_areas.unwrap_or(s("main contrib non-free non-free-firmware"));
-    let paths_to_set = [
-        ("LB_ARCHITECTURE", arch),
-        ("LB_ARCHIVE_AREAS", archive_areas),
-        ("LB_PARENT_ARCHIVE_AREAS",archive_areas)
-    ];
-    for (key, value) in paths_to_set.iter() {
-        cfg_parser::set(key, value, &bootstrap)?;
-    }
+    cross::apply(arch, &mut lb_config)?;
+    lb_config.bootstrap.archive_areas = archive_areas.split_whitespace().map(s).collect();
+    lb_config.bootstrap.parent_archive_areas = lb_config.bootstrap.archive_areas.clone();
 
     
     // apt
     let apt = config.apt.unwrap_or(s("aptitude"));
-    cfg_parser::set("LB_APT", &apt, &common)?;
-    cfg_parser::set("LB_APT_RECOMMENDS", &config.recommends.unwrap_or(true).to_string(), &common)?;
+    lb_config.common.apt = apt.clone();
+    lb_config.common.apt_recommends = config.recommends.unwrap_or(true);
+
+    // debconf preseeding
+    let mut preseed: Option<String> = None;
+    if let Some(selections) = &config.debconf {
+        let content = debconf::render(selections)?;
+        debconf::place(&content, live_dir)?;
+        preseed = Some(content);
+    }
 
     // debootstrap options
-    let include_extras = vec![s("apt-transport-https"),s("ca-certificates,openssl")];
-    let deboot_opts_parsed = deboot_opt::parse(&config.de_boot_opts.unwrap_or(s("")), include_extras);
-    cfg_parser::set("DEBOOTSTRAP_OPTIONS", &deboot_opts_parsed,&common)?;
+    let mut deboot_opts = deboot_opt::DebootOpts::parse(config.de_boot_opts.as_deref().unwrap_or(""))?;
+    if let Some(debootstrap) = &config.debootstrap {
+        deboot_opts.apply(debootstrap)?;
+    }
+    // https://lists.debian.org/debian-live/2021/01/msg00012.html
+    deboot_opts.include(["apt-transport-https", "ca-certificates", "openssl"]);
+    lb_config.common.debootstrap_options = deboot_opts.render()?;
+    let mut distribution_packages: OrderSet<String> = deboot_opts.include.iter().cloned().collect();
+    if let Some(epoch) = source_date_epoch {
+        lb_config.common.source_date_epoch = epoch.to_string();
+    }
+
+    // snapshot mirrors
+    if let Some(spec) = &config.snapshot {
+        snapshot::apply(spec, &mut lb_config, live_dir)?;
+    }
+    if let Some(mirrors) = &config.mirrors {
+        mirrors::apply(mirrors, config.snapshot.as_ref(), &mut lb_config)?;
+    }
+    let port = config.mirrors.as_ref().and_then(|mirrors| mirrors.offline_port).unwrap_or(apt_mirror::PORT);
+    let offline_mirror = args.offline && apt_mirror::use_offline(&mut lb_config, &apt_mirror::dir(&args.out_dir), port);
+    if args.offline && !offline_mirror {
+        eprintln!("W: No apt mirror in {}, the build needs {}", apt_mirror::dir(&args.out_dir).display(), lb_config.bootstrap.mirror_bootstrap);
+    }
+    lb_config.save(live_dir)?;
 
     // configure extra apt packages
     if let Some(extras) = config.extras{
@@ -90,9 +228,11 @@ pub fn apply(args: &Args, live_dir: &Path) -> Result<(), Box<dyn std::error::Err
         let key_path = includes_after_packages.join(format!("tmp/apt-keyrings-cache-debr/{}.gpg", name));
 
         keyrings_parsed.insert(name.to_string(), key.to_string());
-        cfg_parser::add(&repo_src, &archive_include_path)?;
+        create_dir_all(archive_include_path.parent().unwrap())?;
+        write(&archive_include_path, format!("{}\n", repo_src))?;
         place_key(key, &key_path)?;
         includes_from_hook_parsed.extend(extra.add);
+        sources_parsed.push(packages::Source::parse(&extra.src)?);
     };
     
     // keyringer setup
@@ -117,51 +257,152 @@ pub fn apply(args: &Args, live_dir: &Path) -> Result<(), Box<dyn std::error::Err
         includes_parsed.insert(s("pkg-config"));
     }
     
-    // darkMode - dark theme
-    let dark = config.dark_mode.unwrap_or(true);
-    if dark{
-        
-
-        // gsettings live boot configure service
-        let gnome_service_path = includes_after_packages.join("etc/systemd/system/apply_gnome_settings.service");
-        create_dir_all(gnome_service_path.parent().unwrap())?;
-        copy(dir.join("assets/apply_gnome_settings.service"), &gnome_service_path)?;
-        set_permissions(&gnome_service_path, PermissionsExt::from_mode(0o644))?;
-        e_service_parsed.insert(s("apply_gnome_settings.service"));
-        
-        let content = &hooks::gnome_set_dark()?;
-        let script_path = includes_after_packages.join("lib/debr_util_scripts/apply_gnome_settings.sh");
-        create_dir_all(script_path.parent().unwrap())?;
-        write(&script_path, content)?;
-        hooks::chmod_x(script_path)?;
+    // gsettings - system-wide dconf database
+    let mut dconf_db = dconf::Dconf::new();
+    if let Some(gsettings) = &config.gsettings {
+        dconf_db.extend(gsettings)?;
+    }
+
+    // desktop environment settings
+    let mut desktop = desktop::select(&config.desktop.unwrap_or(s("gnome")))?;
+    let mut unsupported: Vec<String> = Vec::new();
+    let mut report = |result: Result<(), Box<dyn std::error::Error>>| {
+        if let Err(e) = result { unsupported.push(e.to_string()); }
+    };
+    if config.dark_mode.unwrap_or(true) {
+        report(desktop.dark_mode());
+    }
+    if let Some(theme) = &config.theme {
+        report(desktop.theme(theme));
+    }
+    if let Some(apps) = &config.favorite_apps {
+        report(desktop.favorite_apps(apps));
+    }
+    if let Some(wallpaper) = &config.wallpaper {
+        let wallpaper = desktop::resolve_wallpaper(wallpaper, &includes_after_packages)?;
+        report(desktop.wallpaper(&wallpaper));
+    }
+    if let Some(apps) = &config.autostart {
+        report(desktop.autostart(apps));
+    }
+    if !unsupported.is_empty() {
+        return Err(unsupported.join("\n").into());
+    }
+    desktop.place(&includes_after_packages, live_dir, &mut dconf_db)?;
+
+    // desktop entries, autostart entries & mime defaults
+    let mut xdg_files = xdg::Xdg::new();
+
+    // verified downloads
+    if let Some(downloads) = &config.downloads {
+        let client = downloads::client()?;
+        let cache_dir = Path::new(&args.out_dir).join("cache/downloads");
+        let mut names: HashSet<&str> = HashSet::new();
+        for download in downloads {
+            if !names.insert(&download.name) {
+                return Err(format!("Duplicate download `{}`", download.name).into());
+            }
+            downloads::place(download, &client, &cache_dir, &includes_after_packages, args.offline)?;
+            if let Some(entry) = &download.desktop_entry {
+                xdg_files.add_application(entry)?;
+            }
+        }
+    }
+    for entry in config.desktop_entries.iter().flatten() {
+        xdg_files.add_application(entry)?;
+    }
+    for entry in config.autostart_entries.iter().flatten() {
+        xdg_files.add_autostart(entry)?;
+    }
+    // the autostart hook would copy the installed launcher over the custom entry
+    for app in config.autostart.iter().flatten() {
+        if xdg_files.has_autostart(app) {
+            return Err(format!("`{}` is in `autostart` and in `autostartEntries`, keep only one of them", app).into());
+        }
+    }
+    for (mime_type, app) in config.mime_defaults.iter().flatten() {
+        xdg_files.add_mime_default(mime_type, app)?;
+    }
+    if !xdg_files.is_empty() {
+        xdg_files.place(&includes_after_packages)?;
+    }
+    if !xdg_files.binaries().is_empty() {
+        let content = hooks::check_binaries(xdg_files.binaries())?;
+        hooks::add_hook("9590-check-desktop-entry-binaries.hook.chroot", &content, live_dir, false)?;
+    }
+
+    if !dconf_db.is_empty() {
+        dconf_db.place(&includes_after_packages)?;
+        includes_parsed.insert(s("dconf-cli"));
+        let content = hooks::dconf_update()?;
+        hooks::add_hook("0520-update-dconf-databases.hook.chroot", &content, live_dir, false)?;
+    }
+
+    // browser managed policies
+    if let Some(browser_policies) = &config.browser_policies {
+        policies::Policies::from_config(browser_policies)?.place(&includes_after_packages)?;
     }
 
     // snap packages
     if let Some(snaps) = config.snaps{
-        snaps_parsed.extend(snaps);
-        println!("Snaps to install: {}\n",snaps_parsed.clone().into_iter().collect::<Vec<String>>().join(" "));
-        
+        for spec in snaps {
+            let snap = spec.into_snap();
+            snap::validate(&snap)?;
+            if let Some(existing) = snaps_parsed.get(&snap.name) {
+                if existing != &snap {
+                    return Err(format!("Conflict in `snaps` for {}\nThisValue:\n{:?}\nOtherValue:\n{:?}", snap.name, existing, snap).into());
+                }
+            }
+            snaps_parsed.insert(snap.name.clone(), snap);
+        }
     }
-    if snaps_parsed.len() != 1 { // first one is "snapd" inserted by default
+    if !snaps_parsed.is_empty() {
+        println!("Snaps to install: {}\n", snaps_parsed.keys().cloned().collect::<Vec<String>>().join(" "));
         includes_parsed.insert(s("snapd"));
 
-        let snap_temp_path = includes_after_packages.join("lib/debr_util_scripts/snap-download-cache");
-        create_dir_all(&snap_temp_path)?;
-        for package in &snaps_parsed {
-            snap::download(package, &arch,&snap_temp_path)?;
+        // snapd seeds the snaps natively on first boot
+        let seed_path = "/var/lib/snapd/seed";
+        let seed_dir = includes_after_packages.join(seed_path.trim_start_matches('/'));
+        remove_dir_all(&seed_dir).ok();
+        let store_url = config.snap_store.clone().unwrap_or(s(store::DEFAULT_URL));
+        let snap_store = store::Store::new(&store_url, arch, &Path::new(&args.out_dir).join("cache/snaps"), args.offline)?;
+        let seeded = snap::resolve(&snaps_parsed, &snap_store, &seed_dir)?;
+        snap::place_model(&snap_store, &seed_dir)?;
+        write(seed_dir.join("seed.yaml"), snap::seed_yaml(&seeded))?;
+
+        let manifest = seeded.iter()
+            .map(|entry| format!(
+                "{}\t{}\t{}\t{}\n",
+                entry.snap.name, entry.revision,
+                entry.snap.channel.clone().unwrap_or(s("latest/stable")),
+                entry.snap.confinement.clone().unwrap_or(s("strict"))
+            ))
+            .collect::<String>();
+        write(live_dir.join("snaps.manifest"), manifest)?;
+
+        let content = hooks::validate_seed(seed_path)?;
+        hooks::add_hook("0540-validate-snapd-seed.hook.chroot", &content, live_dir, false)?;
+    }
+
+    // flatpaks
+    if let Some(flatpaks) = &config.flatpaks {
+        if flatpaks.refs.as_ref().is_some_and(|refs| !refs.is_empty()) {
+            includes_parsed.insert(s("flatpak"));
+            let cache_dir = Path::new(&args.out_dir).join("cache/flatpak");
+            let prefetched = flatpak::prefetch(flatpaks, arch, &cache_dir, &includes_after_packages, args.offline)?;
+            let content = hooks::flatpak_install(&prefetched)?;
+            hooks::add_hook("0550-install-flatpaks.hook.chroot", &content, live_dir, false)?;
         }
+    }
 
-        // snapd live boot install from cache service
-        let snapd_installer_service_path = includes_after_packages.join("etc/systemd/system/snapd_installer.service");
-        create_dir_all(snapd_installer_service_path.parent().unwrap())?;
-        copy(dir.join("assets/snapd_installer.service"), &snapd_installer_service_path)?;
-        set_permissions(&snapd_installer_service_path, PermissionsExt::from_mode(0o644))?;
-        e_service_parsed.insert(s("snapd_installer.service"));
-        
-        let content = hooks::snap_install_from(&snaps_parsed, "/lib/debr_util_scripts/snap-download-cache")?;
-        let script_path = snap_temp_path.join("installer.sh");
-        write(&script_path, content)?;
-        hooks::chmod_x(script_path)?;
+    // pipx, npm & cargo packages
+    if let Some(tool_packages) = &config.tool_packages {
+        let installs = tools::installs(tool_packages)?;
+        if !installs.is_empty() {
+            includes_parsed.extend(tools::apt_packages(tool_packages));
+            let content = hooks::tool_install(&installs)?;
+            hooks::add_hook("0560-install-tool-packages.hook.chroot", &content, live_dir, false)?;
+        }
     }
 
     // enabled//disabled services
@@ -171,30 +412,83 @@ pub fn apply(args: &Args, live_dir: &Path) -> Result<(), Box<dyn std::error::Err
     if let Some(d_service) = config.d_service {
         d_service_parsed.extend(d_service);
     }
-    if d_service_parsed.len() != 0 || e_service_parsed.len() != 0{
+    if !d_service_parsed.is_empty() || !e_service_parsed.is_empty(){
         let content = hooks::services(&e_service_parsed, &d_service_parsed)?;
         hooks::add_hook("0510-update-default-services-status.hook.chroot", &content, live_dir, false)?;
     }
     
-    // apt packages to install
-    if let Some(include) = config.include{
-        includes_parsed.extend(include);
+    // apt packages to install, per list file and stage
+    let mut package_lists = package_lists::PackageLists::new();
+    let mut binary_parsed: OrderSet<String> = OrderSet::new();
+    let mut auto_included: Vec<String> = includes_parsed.drain(..).collect();
+    auto_included.sort();
+    for package in config.include.into_iter().flatten().map(|spec| spec.into_package()) {
+        package_lists.add(&package)?;
+        // packages for other architectures are left to the live-build conditionals
+        if package.arch.as_ref().is_some_and(|archs| !archs.contains(arch)) {
+            continue;
+        }
+        if package.stage.as_deref() == Some("binary") {
+            binary_parsed.insert(package.name);
+        } else {
+            includes_parsed.insert(package.name);
+        }
+    }
+    for name in auto_included {
+        if includes_parsed.insert(name.clone()) {
+            package_lists.add(&json_cfg::Package { name, ..Default::default() })?;
+        }
     }
     
-    let content = includes_parsed.iter().cloned().collect::<Vec<String>>().join("\n");
-    cfg_parser::add(&content, &live_dir.join("config/package-lists/debr_packages.list.chroot"))?;
+    if let Some(purge) = config.purge {
+        purge_parsed.extend(purge);
+    }
 
-    if includes_from_hook_parsed.len() != 0 {
-        let content = hooks::apt_install(&includes_from_hook_parsed, &apt)?;
-        hooks::add_hook("0350-install-apt-packages.hook.chroot", &content, live_dir, false)?;
+    // resolve the packages against the apt indices before building
+    if config.validate_packages.unwrap_or(true) {
+        let mirror = &lb_config.bootstrap.mirror_chroot;
+        sources_parsed.insert(0, packages::Source::parse(&format!("{} {} {}", mirror, dist, archive_areas))?);
+        // offline, the mirror of `debr fetch --packages` is read directly
+        let index_dir = match &config.package_index_dir {
+            Some(index_dir) => Some(PathBuf::from(index_dir)),
+            None if offline_mirror => Some(apt_mirror::dir(&args.out_dir).join("debian")),
+            None => None,
+        };
+        let index = packages::Index::load(&sources_parsed, arch, index_dir.as_deref(), &Path::new(&args.out_dir).join("cache/apt-indices"), args.offline)?;
+
+        let mut missing: Vec<String> = Vec::new();
+        for (source, packages) in [("include", &includes_parsed), ("include", &binary_parsed), ("extras", &includes_from_hook_parsed)] {
+            let mut packages: Vec<&String> = packages.iter().collect();
+            packages.sort();
+            missing.extend(packages.into_iter().filter_map(|p| index.check(p)).map(|e| format!("{}: {}", source, e)));
+        }
+        if !missing.is_empty() {
+            return Err(format!("Unknown packages:\n{}", missing.join("\n")).into());
+        }
+        let mut purged: Vec<&String> = purge_parsed.iter().collect();
+        purged.sort();
+        let unknown: Vec<String> = purged.into_iter().filter_map(|p| index.check(p)).map(|e| format!("purge: {}", e)).collect();
+        lint::report(&unknown, args.strict)?;
     }
 
-    if let Some(purge) = config.purge {
-        purge_parsed.extend(purge);
+    package_lists.place(live_dir)?;
+
+    if !includes_from_hook_parsed.is_empty() {
+        let content = hooks::apt_install(&includes_from_hook_parsed, &apt, preseed.as_deref())?;
+        hooks::add_hook("0350-install-apt-packages.hook.chroot", &content, live_dir, false)?;
     }
-    if purge_parsed.len() != 0{
-        let content = hooks::apt_purge(&purge_parsed)?;
+
+    lint::report(&lint::overlaps(&includes_parsed, &includes_from_hook_parsed, &purge_parsed), args.strict)?;
+    if !purge_parsed.is_empty(){
+        let purged: HashSet<&str> = purge_parsed.iter().map(|p| lint::package_name(p)).collect();
+        let wanted: OrderSet<String> = includes_parsed.iter().chain(&includes_from_hook_parsed)
+            .map(|p| s(lint::package_name(p)))
+            .filter(|p| !purged.contains(p.as_str()))
+            .collect();
+        let content = hooks::apt_purge(&purge_parsed, &wanted, args.strict)?;
         hooks::add_hook("9550-purge-apt-packages.hook.chroot", &content, live_dir, false)?;
     }
-    Ok(())
+    distribution_packages.extend(includes_parsed);
+    distribution_packages.extend(binary_parsed);
+    Ok(Generated { packages: distribution_packages, inputs, source_date_epoch })
 }
@@ -0,0 +1,516 @@
+use std::error::Error;
+use std::fs::{self, create_dir_all, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use ordermap::OrderSet;
+use reqwest::blocking::{Client, ClientBuilder};
+use reqwest::StatusCode;
+use sha2::{Digest, Sha256};
+
+use super::downloads::file_sha256;
+use super::lb_config::LbConfig;
+use super::lint;
+use super::packages::{self, dependency_name, Index, Stanza};
+use super::store::hex_encode;
+
+// where `debr build` serves the mirror, the chroot shares the network of the host
+const HOST: &str = "127.0.0.1";
+// default of `mirrors.offlinePort`
+pub const PORT: u16 = 3149;
+// connections served at the same time, apt and debootstrap use a few at most
+const WORKERS: usize = 8;
+// an idle keep-alive connection is closed after this, so it doesn't hold a worker
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+// compressions of the indices which are mirrored, with the command to decompress them
+const COMPRESSIONS: [(&str, &str); 2] = [(".xz", "xz"), (".gz", "gzip")];
+// installed by live-build besides the configured packages, the ones missing for the architecture are skipped
+const LIVE_PACKAGES: [&str; 21] = [
+    "live-boot", "live-config", "live-config-systemd", "systemd-sysv",
+    "syslinux", "syslinux-common", "syslinux-efi", "isolinux",
+    "grub-common", "grub-pc-bin", "grub-efi-amd64-bin", "grub-efi-ia32-bin", "grub-efi-arm64-bin",
+    "grub-efi-amd64-signed", "grub-efi-arm64-signed", "shim-signed",
+    "mtools", "dosfstools", "xorriso", "squashfs-tools", "zsync",
+];
+
+pub fn dir(out_dir: &str) -> PathBuf {
+    Path::new(out_dir).join("cache/apt-mirror")
+}
+
+fn url(port: u16) -> String {
+    format!("http://{}:{}", HOST, port)
+}
+
+// the port of the served mirror if the build installs from it
+pub fn port(lb_config: &LbConfig) -> Option<u16> {
+    let address = lb_config.bootstrap.mirror_bootstrap.strip_prefix(&format!("http://{}:", HOST))?;
+    address.strip_suffix("/debian/")?.parse().ok()
+}
+
+// points the build at the mirror `debr fetch --packages` created, the image keeps its own mirrors,
+// `false` if there is none
+pub fn use_offline(lb_config: &mut LbConfig, mirror_dir: &Path, port: u16) -> bool {
+    let dist_dir = mirror_dir.join("debian/dists").join(&lb_config.bootstrap.distribution);
+    if !dist_dir.join("Release").exists() && !dist_dir.join("InRelease").exists() {
+        return false;
+    }
+    let bootstrap = &mut lb_config.bootstrap;
+    for mirror in [
+        &mut bootstrap.mirror_bootstrap, &mut bootstrap.parent_mirror_bootstrap,
+        &mut bootstrap.mirror_chroot, &mut bootstrap.parent_mirror_chroot,
+    ] {
+        *mirror = format!("{}/debian/", url(port));
+    }
+    for mirror in [&mut bootstrap.mirror_chroot_security, &mut bootstrap.parent_mirror_chroot_security] {
+        *mirror = format!("{}/debian-security/", url(port));
+    }
+    lb_config.common.apt_http_proxy = String::new();
+    // live-build would update the indices against the image's mirrors at the end of the chroot stage
+    lb_config.binary.apt_indices = false;
+    true
+}
+
+// (mirror url, directory in the mirror, suite) of the suites live-build uses in the chroot
+fn suites(lb_config: &LbConfig) -> Vec<(String, &'static str, String)> {
+    let dist = &lb_config.bootstrap.distribution;
+    let chroot = &lb_config.chroot;
+    let archive = lb_config.bootstrap.mirror_chroot.trim_end_matches('/').to_string();
+    let mut suites = vec![(archive.clone(), "debian", dist.clone())];
+    for (enabled, suffix) in [(chroot.updates, "updates"), (chroot.backports, "backports"), (chroot.proposed_updates, "proposed-updates")] {
+        if enabled {
+            suites.push((archive.clone(), "debian", format!("{}-{}", dist, suffix)));
+        }
+    }
+    if chroot.security {
+        let security = lb_config.bootstrap.mirror_chroot_security.trim_end_matches('/').to_string();
+        suites.push((security, "debian-security", format!("{}-security", dist)));
+    }
+    suites
+}
+
+// mirrors the indices of the build's suites and the packages it installs (with their dependencies) into `mirror_dir`,
+// packages live-build cached in `live_dir` are taken from there
+pub fn fetch(lb_config: &LbConfig, packages: &OrderSet<String>, live_dir: &Path, mirror_dir: &Path) -> Result<(), Box<dyn Error>> {
+    let client = ClientBuilder::new().timeout(None).build()?;
+    let arch = &lb_config.bootstrap.architecture;
+    let mut index = Index::default();
+    // (mirror url, directory in the mirror) by the origin of the stanzas
+    let mut origins: Vec<(String, &str)> = Vec::new();
+    for (url, dir, suite) in suites(lb_config) {
+        let suite_dir = mirror_dir.join(dir).join("dists").join(&suite);
+        // `-updates` and the like don't exist for every distribution
+        if !mirror_suite(&client, &url, &suite, lb_config, &suite_dir)? {
+            if suite == lb_config.bootstrap.distribution {
+                return Err(format!("No Release file found for {} {}", url, suite).into());
+            }
+            println!("Skipping {} {}, it has no Release file", url, suite);
+            continue;
+        }
+        for area in &lb_config.bootstrap.archive_areas {
+            let binary_dir = suite_dir.join(area).join(format!("binary-{}", arch));
+            if let Some((suffix, decompressor)) = COMPRESSIONS.iter().find(|(suffix, _)| binary_dir.join(format!("Packages{}", suffix)).exists()) {
+                let content = packages::decompress(&binary_dir.join(format!("Packages{}", suffix)), Some(decompressor))?;
+                index.parse(&content, origins.len());
+            }
+        }
+        origins.push((url, dir));
+    }
+
+    // what debootstrap and live-build install besides the configured packages
+    let mut seeds: Vec<String> = important(&index);
+    seeds.extend(packages.iter().filter(|p| !p.contains(['*', '?', '['])).map(|p| lint::package_name(p).to_string()));
+    seeds.extend(LIVE_PACKAGES.iter().map(|p| p.to_string()));
+    seeds.extend(lb_config.chroot.keyring_packages.iter().cloned());
+    seeds.push(lb_config.common.apt.clone());
+    for package in &lb_config.chroot.linux_packages {
+        seeds.extend(lb_config.chroot.linux_flavours.iter().map(|flavour| format!("{}-{}", package, flavour)));
+    }
+    if lb_config.binary.firmware_chroot || lb_config.binary.firmware_binary {
+        seeds.extend(firmware(&index));
+    }
+    let resolved = resolve(&index, seeds, lb_config.common.apt_recommends);
+
+    import_cache(&index, &origins, live_dir, mirror_dir)?;
+    let stanzas: Vec<&Stanza> = resolved.iter().flat_map(|name| &index.packages[name]).collect();
+    println!("Mirroring {} packages into {}", stanzas.len(), mirror_dir.display());
+    for (position, stanza) in stanzas.iter().enumerate() {
+        let (url, dir) = &origins[stanza.origin];
+        let target = mirror_dir.join(dir).join(&stanza.filename);
+        if target.exists() && file_sha256(&target)? == stanza.sha256 {
+            continue;
+        }
+        println!("[{:>5}/{}] {}", position + 1, stanzas.len(), stanza.filename);
+        download_deb(&client, url, stanza, &target)?;
+    }
+    Ok(())
+}
+
+// downloads the Release files and the indices apt and live-build read, `false` if the suite doesn't exist
+fn mirror_suite(client: &Client, url: &str, suite: &str, lb_config: &LbConfig, suite_dir: &Path) -> Result<bool, Box<dyn Error>> {
+    let base = format!("{}/dists/{}", url, suite);
+    create_dir_all(suite_dir)?;
+    let mut release: Option<String> = None;
+    for name in ["Release", "InRelease", "Release.gpg"] {
+        match get(client, &format!("{}/{}", base, name))? {
+            Some(content) => {
+                fs::write(suite_dir.join(name), &content)?;
+                release.get_or_insert_with(|| String::from_utf8_lossy(&content).to_string());
+            }
+            None => {
+                fs::remove_file(suite_dir.join(name)).ok();
+            }
+        }
+    }
+    let Some(release) = release else {
+        return Ok(false);
+    };
+
+    let entries = packages::release_sha256(&release);
+    let arch = &lb_config.bootstrap.architecture;
+    for (path, sha256) in &entries {
+        if !is_wanted(path, &lb_config.bootstrap.archive_areas, arch, lb_config.common.apt_source_archives) {
+            continue;
+        }
+        // uncompressed indices are listed but not served, other compressions aren't needed
+        let compressed = COMPRESSIONS.iter().any(|(suffix, _)| path.ends_with(suffix));
+        if !compressed && (COMPRESSIONS.iter().any(|(suffix, _)| entries.contains_key(&format!("{}{}", path, suffix)))
+            || [".bz2", ".lzma", ".zst", ".lz4"].iter().any(|suffix| path.ends_with(suffix)))
+        {
+            continue;
+        }
+        let target = suite_dir.join(path);
+        if !(target.exists() && file_sha256(&target)? == *sha256) {
+            let Some(content) = get(client, &format!("{}/{}", base, path))? else {
+                continue;
+            };
+            if hex_encode(&Sha256::digest(&content)) != *sha256 {
+                return Err(format!("sha256 mismatch for {}/{}", base, path).into());
+            }
+            create_dir_all(target.parent().unwrap())?;
+            fs::write(&target, &content)?;
+        }
+        // apt requests the indices by their hash if the Release file says so
+        let by_hash = target.parent().unwrap().join("by-hash/SHA256");
+        create_dir_all(&by_hash)?;
+        fs::remove_file(by_hash.join(sha256)).ok();
+        fs::hard_link(&target, by_hash.join(sha256))?;
+    }
+    Ok(true)
+}
+
+// indices of the architecture and `all`, of the archive areas, translations in english only
+fn is_wanted(path: &str, areas: &[String], arch: &str, sources: bool) -> bool {
+    let Some((area, rest)) = path.split_once('/') else {
+        return false;
+    };
+    if !areas.iter().any(|a| a == area) {
+        return false;
+    }
+    let for_arch = |name: &str| [arch, "all"].iter().any(|a| name.contains(&format!("-{}.", a)));
+    match rest.split_once('/') {
+        None => rest.starts_with("Contents-") && !rest.starts_with("Contents-udeb") && for_arch(rest),
+        Some(("source", _)) => sources,
+        Some(("i18n", name)) => name.starts_with("Translation-en."),
+        Some(("dep11", name)) => name.starts_with("icons-") || for_arch(name),
+        Some(("cnf", name)) => for_arch(name),
+        Some((dir, _)) => *dir == format!("binary-{}", arch) || dir == "binary-all",
+    }
+}
+
+// `None` if the file doesn't exist on the mirror
+fn get(client: &Client, url: &str) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    let response = client.get(url).send()?;
+    if response.status() == StatusCode::NOT_FOUND || response.status() == StatusCode::FORBIDDEN {
+        return Ok(None);
+    }
+    if !response.status().is_success() {
+        return Err(format!("Failed to download {}: {}", url, response.status()).into());
+    }
+    Ok(Some(response.bytes()?.to_vec()))
+}
+
+fn download_deb(client: &Client, url: &str, stanza: &Stanza, target: &Path) -> Result<(), Box<dyn Error>> {
+    let url = format!("{}/{}", url, stanza.filename);
+    let mut response = client.get(&url).send()?;
+    if !response.status().is_success() {
+        return Err(format!("Failed to download {}: {}", url, response.status()).into());
+    }
+    create_dir_all(target.parent().unwrap())?;
+    let partial = target.with_extension("part");
+    io::copy(&mut response, &mut File::create(&partial)?)?;
+    let digest = file_sha256(&partial)?;
+    if digest != stanza.sha256 {
+        fs::remove_file(&partial).ok();
+        return Err(format!("sha256 mismatch for {}: expected {}, got {}", url, stanza.sha256, digest).into());
+    }
+    fs::rename(&partial, target)?;
+    Ok(())
+}
+
+// packages of a previous build from live-build's cache, `<name>_<version>_<arch>.deb` with `:` encoded as `%3a`
+fn import_cache(index: &Index, origins: &[(String, &str)], live_dir: &Path, mirror_dir: &Path) -> Result<(), Box<dyn Error>> {
+    for stage in ["bootstrap", "chroot", "binary"] {
+        let Ok(entries) = fs::read_dir(live_dir.join(format!("cache/packages.{}", stage))) else {
+            continue;
+        };
+        for entry in entries {
+            let path = entry?.path();
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy().replace("%3a", ":");
+            let parts: Vec<&str> = file_name.trim_end_matches(".deb").split('_').collect();
+            let [name, version, architecture] = parts[..] else {
+                continue;
+            };
+            let Some(stanza) = index.packages.get(name).into_iter().flatten()
+                .find(|s| s.version == version && s.architecture == architecture) else {
+                continue;
+            };
+            let target = mirror_dir.join(origins[stanza.origin].1).join(&stanza.filename);
+            if !target.exists() && file_sha256(&path)? == stanza.sha256 {
+                create_dir_all(target.parent().unwrap())?;
+                fs::copy(&path, &target)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// the packages debootstrap installs
+fn important(index: &Index) -> Vec<String> {
+    index.names.iter()
+        .filter(|name| index.packages[*name].iter().any(|s| s.priority == "required" || s.priority == "important"))
+        .cloned()
+        .collect()
+}
+
+// firmware live-build may pick from the archive areas
+fn firmware(index: &Index) -> Vec<String> {
+    index.names.iter()
+        .filter(|name| name.starts_with("firmware-") || index.packages[*name].iter().any(|s| s.section.starts_with("non-free-firmware")))
+        .cloned()
+        .collect()
+}
+
+// dependency closure, taking the first available alternative of every dependency,
+// this may include a few packages apt wouldn't pick, but keeps all the ones it does
+fn resolve(index: &Index, seeds: Vec<String>, recommends: bool) -> OrderSet<String> {
+    let mut resolved: OrderSet<String> = OrderSet::new();
+    let mut queue = seeds;
+    queue.reverse();
+    while let Some(name) = queue.pop() {
+        let Some(name) = index.candidate(&name) else {
+            continue;
+        };
+        if !resolved.insert(name.clone()) {
+            continue;
+        }
+        for stanza in &index.packages[&name] {
+            let recommended = stanza.recommends.iter().filter(|_| recommends);
+            for group in stanza.depends.iter().chain(recommended) {
+                if let Some(alternative) = group.split('|').map(dependency_name).find(|a| index.candidate(a).is_some()) {
+                    queue.push(alternative.to_string());
+                }
+            }
+        }
+    }
+    resolved
+}
+
+// serves `mirror_dir` on `port` in the background for as long as debr runs
+pub fn serve(mirror_dir: &Path, port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind((HOST, port)).map_err(|e| io::Error::other(format!(
+        "Failed to serve the apt mirror on {}:{}: {}, set another port with `mirrors.offlinePort` and run `debr --offline config` again",
+        HOST, port, e
+    )))?;
+    for _ in 0..WORKERS {
+        let listener = listener.try_clone()?;
+        let root = mirror_dir.to_path_buf();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                respond(stream, &root).ok();
+            }
+        });
+    }
+    println!("Serving the apt mirror {} on {}", mirror_dir.display(), url(port));
+    Ok(())
+}
+
+// GET and HEAD of files, the connection is kept open for further (pipelined) requests
+// unless the client asks to close it
+fn respond(mut stream: TcpStream, root: &Path) -> io::Result<()> {
+    stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    loop {
+        let mut request = String::new();
+        if reader.read_line(&mut request)? == 0 {
+            return Ok(());
+        }
+        if request.trim().is_empty() {
+            continue;
+        }
+        let mut parts = request.split_whitespace();
+        let (method, target) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+        // HTTP/1.0 closes by default, HTTP/1.1 keeps the connection
+        let mut close = parts.next() != Some("HTTP/1.1");
+        let mut header = String::new();
+        loop {
+            header.clear();
+            if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("connection") {
+                    close = value.trim().eq_ignore_ascii_case("close");
+                }
+            }
+        }
+
+        let connection = if close { "close" } else { "keep-alive" };
+        let path = percent_decode(target.split('?').next().unwrap_or_default());
+        let file = root.join(path.trim_start_matches('/'));
+        if !["GET", "HEAD"].contains(&method) || path.split('/').any(|segment| segment == "..") || !file.is_file() {
+            write!(stream, "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: {}\r\n\r\n", connection)?;
+        } else {
+            let mut content = File::open(&file)?;
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: {}\r\n\r\n", content.metadata()?.len(), connection)?;
+            if method == "GET" {
+                io::copy(&mut content, &mut stream)?;
+            }
+        }
+        stream.flush()?;
+        if close {
+            return Ok(());
+        }
+    }
+}
+
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded: Vec<u8> = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if let Some(byte) = path.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn index() -> Index {
+        let mut index = Index::default();
+        index.parse("Package: a
+Priority: required
+Depends: b (>= 1), c | d
+Recommends: e
+
+Package: b
+Pre-Depends: awk
+
+Package: c
+Priority: optional
+Depends: a
+
+Package: d
+
+Package: e
+
+Package: mawk
+Provides: awk
+", 0);
+        index
+    }
+
+    fn s(value: &str) -> String {
+        value.to_string()
+    }
+
+    #[test]
+    fn resolves_dependencies() {
+        let index = index();
+        assert_eq!(important(&index), ["a"]);
+        let mut resolved: Vec<String> = resolve(&index, vec![s("a"), s("missing")], false).into_iter().collect();
+        resolved.sort();
+        assert_eq!(resolved, ["a", "b", "c", "mawk"]);
+        let resolved = resolve(&index, vec![s("a")], true);
+        assert!(resolved.contains("e") && !resolved.contains("d"));
+    }
+
+    #[test]
+    fn wanted_indices() {
+        let areas = [s("main"), s("non-free-firmware")];
+        let wanted = |path| is_wanted(path, &areas, "amd64", false);
+        assert!(wanted("main/binary-amd64/Packages.xz"));
+        assert!(wanted("main/binary-all/Packages.xz"));
+        assert!(wanted("non-free-firmware/i18n/Translation-en.bz2"));
+        assert!(wanted("main/Contents-amd64.gz"));
+        assert!(wanted("main/dep11/icons-64x64.tar.gz"));
+        assert!(wanted("main/dep11/Components-amd64.yml.gz"));
+        assert!(!wanted("main/binary-arm64/Packages.xz"));
+        assert!(!wanted("contrib/binary-amd64/Packages.xz"));
+        assert!(!wanted("main/i18n/Translation-de.bz2"));
+        assert!(!wanted("main/Contents-udeb-amd64.gz"));
+        assert!(!wanted("main/source/Sources.xz"));
+        assert!(is_wanted("main/source/Sources.xz", &areas, "amd64", true));
+        assert!(!wanted("Release"));
+    }
+
+    #[test]
+    fn percent_decoding() {
+        assert_eq!(percent_decode("/pool/main/g/gcc/libgcc-s1_12.2.0-14%2bdeb12u1_amd64.deb"), "/pool/main/g/gcc/libgcc-s1_12.2.0-14+deb12u1_amd64.deb");
+        assert_eq!(percent_decode("/a%3ab"), "/a:b");
+        assert_eq!(percent_decode("/100%"), "/100%");
+        assert_eq!(percent_decode("/%zz"), "/%zz");
+    }
+
+    #[test]
+    fn keeps_connections_open() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("Release"), "Suite: bookworm\n").unwrap();
+        let listener = TcpListener::bind((HOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let root_path = root.path().to_path_buf();
+        let server = thread::spawn(move || respond(listener.accept().unwrap().0, &root_path));
+
+        // pipelined, the last one closes the connection
+        let mut client = TcpStream::connect(address).unwrap();
+        client.write_all(concat!(
+            "GET /Release HTTP/1.1\r\nHost: x\r\n\r\n",
+            "HEAD /Release HTTP/1.1\r\n\r\n",
+            "GET /../etc/passwd HTTP/1.1\r\n\r\n",
+            "GET /missing HTTP/1.1\r\nConnection: close\r\n\r\n",
+        ).as_bytes()).unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        server.join().unwrap().unwrap();
+        assert_eq!(response, concat!(
+            "HTTP/1.1 200 OK\r\nContent-Length: 16\r\nConnection: keep-alive\r\n\r\nSuite: bookworm\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: 16\r\nConnection: keep-alive\r\n\r\n",
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: keep-alive\r\n\r\n",
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        ));
+
+        // HTTP/1.0 closes after one request
+        let listener = TcpListener::bind((HOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let root_path = root.path().to_path_buf();
+        let server = thread::spawn(move || respond(listener.accept().unwrap().0, &root_path));
+        let mut client = TcpStream::connect(address).unwrap();
+        client.write_all(b"HEAD /Release HTTP/1.0\r\n\r\n").unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        server.join().unwrap().unwrap();
+        assert_eq!(response, "HTTP/1.1 200 OK\r\nContent-Length: 16\r\nConnection: close\r\n\r\n");
+    }
+}
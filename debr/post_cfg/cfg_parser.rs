@@ -1,130 +1,523 @@
-use std::fs::{self,OpenOptions};
-use std::io::{self, Read, Write, Seek, SeekFrom, BufRead, BufReader};
-use std::path::Path;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 
+// a live-build shell config file (config/common, config/bootstrap, ...)
+// only assignments are interpreted, everything else is kept verbatim
+pub struct ShellConfig {
+    path: PathBuf,
+    original: String,
+    items: Vec<Item>,
+}
+
+enum Item {
+    Assignment(Assignment),
+    Other(String),
+}
 
-pub fn add(content: &str, path: &Path) -> io::Result<()> {
-    if let Some(parent) = path.parent() {
-        fs::create_dir_all(parent)?;
+struct Assignment {
+    // text of the assignment including its newline, written back unless changed
+    raw: String,
+    indent: String,
+    export: bool,
+    key: String,
+    value: String,
+    // contains `$VAR`, `$(...)` or backticks which are kept unexpanded in `value`
+    dynamic: bool,
+    // whatever follows the value up to the end of the line, e.g. a comment, or up to the next command after `;`
+    tail: String,
+}
+
+impl ShellConfig {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let items = parse(&content).map_err(|e| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Failed to parse {}: {}", path.display(), e),
+        ))?;
+        Ok(ShellConfig { path: path.to_path_buf(), original: content, items })
     }
 
-    if path.exists() {
-        let file = fs::File::open(path)?;
-        let reader = BufReader::new(file);
-        if reader.lines().any(|line| line.as_ref().map(|l| l == content).unwrap_or(false)) {
-            return Ok(());
+    // without a file, e.g. to compare values, `save` doesn't apply
+    pub fn new() -> Self {
+        ShellConfig { path: PathBuf::new(), original: String::new(), items: Vec::new() }
+    }
+
+    // value of the last assignment, as the shell would see it
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.assignment(key).map(|a| a.value.as_str())
+    }
+
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.get(key)? {
+            "true" => Some(true),
+            "false" => Some(false),
+            _ => None,
         }
     }
 
-    let mut file = OpenOptions::new()
-        .write(true)
-        .append(true)
-        .create(true)
-        .open(path)?;
+    // whitespace separated values such as LB_BOOTAPPEND_LIVE
+    pub fn get_list(&self, key: &str) -> Option<Vec<String>> {
+        self.get(key).map(|value| value.split_whitespace().map(|v| v.to_string()).collect())
+    }
 
-    writeln!(file, "\n{}", content)?;
+    // whether the value contains expansions which are kept unexpanded
+    pub fn is_dynamic(&self, key: &str) -> bool {
+        self.assignment(key).is_some_and(|a| a.dynamic)
+    }
 
-    Ok(())
-}
+    // assigned variables in order of their first assignment
+    pub fn keys(&self) -> Vec<&str> {
+        let mut keys: Vec<&str> = Vec::new();
+        for item in &self.items {
+            if let Item::Assignment(a) = item {
+                if !keys.contains(&a.key.as_str()) {
+                    keys.push(&a.key);
+                }
+            }
+        }
+        keys
+    }
 
-pub fn strip(content: &str, path: &Path) -> io::Result<()> {
-    // Return early if the file doesn't exist
-    if !path.exists() {
-        return Ok(());
+    // rewrites the last assignment of the key, keeping `export` and trailing comments, or appends one
+    pub fn set(&mut self, key: &str, value: &str) {
+        if let Some(assignment) = self.assignment_mut(key) {
+            if assignment.value == value && !assignment.dynamic {
+                return;
+            }
+            assignment.value = value.to_string();
+            assignment.dynamic = false;
+            assignment.raw = format!(
+                "{}{}{}={}{}",
+                assignment.indent, if assignment.export { "export " } else { "" }, key, quote(value), assignment.tail
+            );
+            return;
+        }
+        match self.items.last_mut() {
+            Some(Item::Other(last)) if !last.ends_with('\n') => last.push('\n'),
+            Some(Item::Assignment(last)) if !last.raw.ends_with('\n') => {
+                last.raw.push('\n');
+                last.tail.push('\n');
+            }
+            _ => {}
+        }
+        self.items.push(Item::Assignment(Assignment {
+            raw: format!("{}={}\n", key, quote(value)),
+            indent: String::new(),
+            export: false,
+            key: key.to_string(),
+            value: value.to_string(),
+            dynamic: false,
+            tail: String::from("\n"),
+        }));
     }
 
-    let file = fs::File::open(path)?;
-    let reader = BufReader::new(file);
+    pub fn set_bool(&mut self, key: &str, value: bool) {
+        self.set(key, if value { "true" } else { "false" });
+    }
 
-    // Filter out lines containing the content
-    let filtered_lines: Vec<String> = reader
-        .lines()
-        .filter_map(|line| line.ok())
-        .filter(|line| line != content)
-        .collect();
+    // whitespace separated, values must not contain whitespace themselves to be read back by `get_list`
+    pub fn set_list(&mut self, key: &str, values: &[String]) {
+        self.set(key, &values.join(" "));
+    }
 
-    // Rewrite the file with the filtered content
-    let mut file = fs::File::create(path)?;
-    for line in filtered_lines {
-        writeln!(file, "{}", line)?;
+    // removes every assignment of the key, live-build falls back to its default
+    #[allow(dead_code)]
+    pub fn unset(&mut self, key: &str) {
+        self.items.retain(|item| !matches!(item, Item::Assignment(a) if a.key == key));
     }
 
-    Ok(())
-}
+    pub fn render(&self) -> String {
+        self.items.iter().map(|item| match item {
+            Item::Assignment(assignment) => assignment.raw.as_str(),
+            Item::Other(raw) => raw.as_str(),
+        }).collect()
+    }
+
+    // writes through a temporary file, untouched files keep their mtime
+    pub fn save(&self) -> io::Result<()> {
+        let content = self.render();
+        if content == self.original {
+            return Ok(());
+        }
+        let tmp_path = self.path.with_extension("debr-tmp");
+        fs::write(&tmp_path, content)?;
+        fs::rename(&tmp_path, &self.path)
+    }
 
+    fn assignment(&self, key: &str) -> Option<&Assignment> {
+        self.items.iter().rev().find_map(|item| match item {
+            Item::Assignment(a) if a.key == key => Some(a),
+            _ => None,
+        })
+    }
 
-pub struct KeyValuePosition {
-    pub value: String,
-    pub start_pos: u64,
-    pub end_pos: u64,
+    fn assignment_mut(&mut self, key: &str) -> Option<&mut Assignment> {
+        self.items.iter_mut().rev().find_map(|item| match item {
+            Item::Assignment(a) if a.key == key => Some(a),
+            _ => None,
+        })
+    }
+}
+
+// double quoted, escaping what the shell would interpret
+pub fn quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
 }
 
-pub fn get(key: &str, path: &Path) -> io::Result<Option<KeyValuePosition>> {
-    let file = OpenOptions::new().read(true).open(path)?;
-    let mut reader = std::io::BufReader::new(file);
-    let mut position: u64 = 0;
-    let mut line = String::new();
+fn parse(content: &str) -> Result<Vec<Item>, String> {
+    let chars: Vec<char> = content.chars().collect();
+    let mut items = Vec::new();
+    let mut pos = 0;
+    let mut line = 1;
+    while pos < chars.len() {
+        let start = pos;
+        match parse_assignment(&chars, &mut pos).map_err(|e| format!("line {}: {}", line, e))? {
+            Some(mut assignment) => {
+                assignment.raw = chars[start..pos].iter().collect();
+                items.push(Item::Assignment(assignment));
+            }
+            None => {
+                pos = start;
+                while pos < chars.len() && chars[pos] != '\n' {
+                    pos += 1;
+                }
+                pos = (pos + 1).min(chars.len());
+                items.push(Item::Other(chars[start..pos].iter().collect()));
+            }
+        }
+        line += chars[start..pos].iter().filter(|c| **c == '\n').count();
+    }
+    Ok(items)
+}
 
-    while reader.read_line(&mut line)? > 0 {
+// `[export ]NAME=value[ tail]\n` or `[export ]NAME=value; ` followed by another command, `None` for anything else
+fn parse_assignment(chars: &[char], pos: &mut usize) -> Result<Option<Assignment>, String> {
+    let at = |i: usize| chars.get(i).copied();
+    let start = *pos;
+    while matches!(at(*pos), Some(' ' | '\t')) {
+        *pos += 1;
+    }
+    let indent: String = chars[start..*pos].iter().collect();
 
-        if line.trim_start().starts_with('#') {
-            position += line.len() as u64;
-            line.clear();
-            continue;
+    let mut export = false;
+    if chars[*pos..].starts_with(&['e', 'x', 'p', 'o', 'r', 't']) && matches!(at(*pos + 6), Some(' ' | '\t')) {
+        export = true;
+        *pos += 6;
+        while matches!(at(*pos), Some(' ' | '\t')) {
+            *pos += 1;
         }
+    }
+
+    let key_start = *pos;
+    if !at(*pos).is_some_and(|c| c.is_ascii_alphabetic() || c == '_') {
+        return Ok(None);
+    }
+    while at(*pos).is_some_and(|c| c.is_ascii_alphanumeric() || c == '_') {
+        *pos += 1;
+    }
+    let key: String = chars[key_start..*pos].iter().collect();
+    if at(*pos) != Some('=') {
+        return Ok(None);
+    }
+    *pos += 1;
 
-        // Found key
-        if line.starts_with(key) {
-            let mut parts = line.splitn(2, '=');
-            let key_found = parts.next().unwrap_or("").trim();
-            let value = parts.next().unwrap_or("").trim_matches('"').trim();
-
-            // Ensure that the key matches exactly
-            if key_found == key {
-                let end_pos = position + line.len() as u64;
-                return Ok(Some(KeyValuePosition {
-                    value: value.to_string(),
-                    start_pos: position,
-                    end_pos,
-                }));
+    let mut value = String::new();
+    let mut dynamic = false;
+    while let Some(c) = at(*pos) {
+        match c {
+            ' ' | '\t' | '\n' | ';' => break,
+            '\\' => {
+                match at(*pos + 1) {
+                    Some('\n') => {}
+                    Some(escaped) => value.push(escaped),
+                    None => {
+                        value.push('\\');
+                        *pos += 1;
+                        continue;
+                    }
+                }
+                *pos += 2;
+            }
+            '\'' => {
+                *pos += 1;
+                while at(*pos) != Some('\'') {
+                    value.push(at(*pos).ok_or("unterminated single quote")?);
+                    *pos += 1;
+                }
+                *pos += 1;
+            }
+            '"' => {
+                *pos += 1;
+                loop {
+                    match at(*pos).ok_or("unterminated double quote")? {
+                        '"' => break,
+                        '\\' if matches!(at(*pos + 1), Some('"' | '\\' | '$' | '`' | '\n')) => {
+                            if at(*pos + 1) != Some('\n') {
+                                value.push(chars[*pos + 1]);
+                            }
+                            *pos += 2;
+                        }
+                        '$' | '`' => {
+                            dynamic = true;
+                            value.push_str(&expansion(chars, pos)?);
+                        }
+                        other => {
+                            value.push(other);
+                            *pos += 1;
+                        }
+                    }
+                }
+                *pos += 1;
+            }
+            '$' | '`' => {
+                dynamic = true;
+                value.push_str(&expansion(chars, pos)?);
+            }
+            other => {
+                value.push(other);
+                *pos += 1;
             }
         }
+    }
 
-        position += line.len() as u64;
-        line.clear(); // Clear buffer
+    let tail_start = *pos;
+    while matches!(at(*pos), Some(' ' | '\t')) {
+        *pos += 1;
     }
+    // `A=1; B=2`, the next command is parsed on its own
+    if at(*pos) == Some(';') {
+        *pos += 1;
+        while matches!(at(*pos), Some(' ' | '\t')) {
+            *pos += 1;
+        }
+        if !matches!(at(*pos), None | Some('\n' | '#')) {
+            let tail = chars[tail_start..*pos].iter().collect();
+            return Ok(Some(Assignment { raw: String::new(), indent, export, key, value, dynamic, tail }));
+        }
+    }
+    while at(*pos).is_some_and(|c| c != '\n') {
+        *pos += 1;
+    }
+    if at(*pos) == Some('\n') {
+        *pos += 1;
+    }
+    let tail = chars[tail_start..*pos].iter().collect();
+    Ok(Some(Assignment { raw: String::new(), indent, export, key, value, dynamic, tail }))
+}
 
-    Ok(None)
+// `$VAR`, `${...}`, `$(...)` or a backtick command, returned unexpanded
+fn expansion(chars: &[char], pos: &mut usize) -> Result<String, String> {
+    let start = *pos;
+    let (open, close) = match (chars[*pos], chars.get(*pos + 1)) {
+        ('`', _) => ('`', '`'),
+        ('$', Some('(')) => ('(', ')'),
+        ('$', Some('{')) => ('{', '}'),
+        _ => {
+            *pos += 1;
+            while chars.get(*pos).is_some_and(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                *pos += 1;
+            }
+            return Ok(chars[start..*pos].iter().collect());
+        }
+    };
+    *pos += if open == '`' { 1 } else { 2 };
+    let mut depth = 1;
+    while depth > 0 {
+        let c = *chars.get(*pos).ok_or(format!("unterminated `{}`", open))?;
+        if c == '\\' {
+            *pos += 1;
+        } else if c == close {
+            depth -= 1;
+        } else if c == open && open != '`' {
+            depth += 1;
+        }
+        *pos += 1;
+    }
+    Ok(chars[start..*pos].iter().collect())
 }
 
-pub fn set(key: &str, value: &str, path: &Path) -> io::Result<()> {
-    if let Some(kv_position) = get(key, path)? {
-        if kv_position.value != value {
-            let mut file = OpenOptions::new().write(true).open(path)?;
-            file.seek(SeekFrom::Start(kv_position.start_pos))?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            let new_data = format!("{}=\"{}\"\n", key, value);
-            let new_data_bytes = new_data.as_bytes();
+    fn parsed(content: &str) -> ShellConfig {
+        ShellConfig { path: PathBuf::new(), original: content.to_string(), items: parse(content).unwrap() }
+    }
 
-            let mut remaining_content = Vec::new();
-            let mut file_for_reading = OpenOptions::new().read(true).open(path)?;
-            file_for_reading.seek(SeekFrom::Start(kv_position.end_pos))?;
-            file_for_reading.read_to_end(&mut remaining_content)?;
+    const COMMON: &str = r#"# config/common - options for live-build(7), common stage
 
-            let mut full_data = Vec::new();
-            full_data.extend_from_slice(new_data_bytes);
-            full_data.extend_from_slice(&remaining_content);
+# $LB_APT: set package manager
+# (Default: apt)
+LB_APT="apt"
 
-            file.write_all(&full_data)?;
+  export LB_MODE="debian"   # keep
+APT_OPTIONS="--yes -o Acquire::Retries=5"
+LB_ISO_VOLUME="Debian $(date --utc -d@${SOURCE_DATE_EPOCH} +%Y%m%d-%H:%M)"
+LB_BUILD=`uname -m`
+LB_ISO_PUBLISHER="Debian Live \"project\"; https://wiki.debian.org/DebianLive"
+LB_SINGLE='a b'
+LB_BOOTAPPEND_LIVE="boot=live
+components quiet"
+LB_ESCAPED=a\ b
+if [ -n "$X" ]; then
+    LB_INDENTED="x"
+fi
+"#;
 
-            let pos = file.stream_position()?;
-            file.set_len(pos)?;
-        }
-    } else {
-        let mut file = OpenOptions::new().write(true).append(true).open(path)?;
-        let new_data = format!("\n{}=\"{}\"", key, value);
-        file.write_all(new_data.as_bytes())?;
+    #[test]
+    fn render_is_identical_after_load() {
+        assert_eq!(parsed(COMMON).render(), COMMON);
+        let without_newline = "A=1\n# last line without newline";
+        assert_eq!(parsed(without_newline).render(), without_newline);
+        assert_eq!(parsed("").render(), "");
+    }
+
+    #[test]
+    fn values() {
+        let config = parsed(COMMON);
+        assert_eq!(config.get("LB_APT"), Some("apt"));
+        assert_eq!(config.get("LB_MODE"), Some("debian"));
+        assert_eq!(config.get("APT_OPTIONS"), Some("--yes -o Acquire::Retries=5"));
+        assert_eq!(config.get("LB_ISO_PUBLISHER"), Some("Debian Live \"project\"; https://wiki.debian.org/DebianLive"));
+        assert_eq!(config.get("LB_SINGLE"), Some("a b"));
+        assert_eq!(config.get("LB_BOOTAPPEND_LIVE"), Some("boot=live\ncomponents quiet"));
+        assert_eq!(config.get("LB_ESCAPED"), Some("a b"));
+        assert_eq!(config.get("LB_INDENTED"), Some("x"));
+        assert_eq!(config.get("LB_MISSING"), None);
+        assert_eq!(
+            config.keys(),
+            ["LB_APT", "LB_MODE", "APT_OPTIONS", "LB_ISO_VOLUME", "LB_BUILD", "LB_ISO_PUBLISHER", "LB_SINGLE", "LB_BOOTAPPEND_LIVE", "LB_ESCAPED", "LB_INDENTED"]
+        );
+    }
+
+    #[test]
+    fn typed_values() {
+        let config = parsed("A=true\nB=false\nC=yes\nD=\"a  b\tc\"\nE=\n");
+        assert_eq!(config.get_bool("A"), Some(true));
+        assert_eq!(config.get_bool("B"), Some(false));
+        assert_eq!(config.get_bool("C"), None);
+        assert_eq!(config.get_list("D"), Some(vec!["a".to_string(), "b".to_string(), "c".to_string()]));
+        assert_eq!(config.get_list("E"), Some(Vec::new()));
+    }
+
+    #[test]
+    fn expansions_are_kept_unexpanded() {
+        let config = parsed(COMMON);
+        assert_eq!(config.get("LB_ISO_VOLUME"), Some("Debian $(date --utc -d@${SOURCE_DATE_EPOCH} +%Y%m%d-%H:%M)"));
+        assert!(config.is_dynamic("LB_ISO_VOLUME"));
+        assert_eq!(config.get("LB_BUILD"), Some("`uname -m`"));
+        assert!(config.is_dynamic("LB_BUILD"));
+        assert!(!config.is_dynamic("LB_APT"));
+    }
+
+    #[test]
+    fn last_assignment_wins() {
+        let config = parsed("A=1\nA=2\n");
+        assert_eq!(config.get("A"), Some("2"));
+        assert_eq!(config.keys(), ["A"]);
+    }
+
+    #[test]
+    fn semicolon_separated_assignments() {
+        let content = "A=1; B=\"2\";C=3 ; # comment\nD=4;\n";
+        let mut config = parsed(content);
+        assert_eq!(config.render(), content);
+        assert_eq!(config.get("A"), Some("1"));
+        assert_eq!(config.get("B"), Some("2"));
+        assert_eq!(config.get("C"), Some("3"));
+        assert_eq!(config.get("D"), Some("4"));
+
+        config.set("B", "two");
+        config.set("C", "three");
+        assert_eq!(config.render(), "A=1; B=\"two\";C=\"three\" ; # comment\nD=4;\n");
+    }
+
+    #[test]
+    fn other_commands_are_kept() {
+        let content = "A=1; echo \"$A\"\nB=2 && true\n";
+        let config = parsed(content);
+        assert_eq!(config.render(), content);
+        assert_eq!(config.get("A"), Some("1"));
+        assert_eq!(config.keys(), ["A", "B"]);
+    }
+
+    #[test]
+    fn set_existing_key() {
+        let mut config = parsed(COMMON);
+        config.set("LB_APT", "apt");
+        assert_eq!(config.render(), COMMON);
+
+        config.set("LB_MODE", "ubuntu");
+        config.set("LB_ISO_PUBLISHER", "a \"b\" $c `d` \\e");
+        config.set("LB_ISO_VOLUME", "static");
+        let rendered = config.render();
+        assert!(rendered.contains("\n  export LB_MODE=\"ubuntu\"   # keep\n"));
+        assert!(rendered.contains("\nLB_ISO_PUBLISHER=\"a \\\"b\\\" \\$c \\`d\\` \\\\e\"\n"));
+        assert!(rendered.contains("\nLB_ISO_VOLUME=\"static\"\n"));
+
+        let reloaded = parsed(&rendered);
+        assert_eq!(reloaded.get("LB_MODE"), Some("ubuntu"));
+        assert_eq!(reloaded.get("LB_ISO_PUBLISHER"), Some("a \"b\" $c `d` \\e"));
+        assert!(!reloaded.is_dynamic("LB_ISO_PUBLISHER"));
+        assert_eq!(reloaded.get("LB_ISO_VOLUME"), Some("static"));
+    }
+
+    #[test]
+    fn set_new_key() {
+        let mut config = parsed("A=1");
+        config.set("B", "2");
+        assert_eq!(config.render(), "A=1\nB=\"2\"\n");
+        config.set("A", "one");
+        assert_eq!(config.render(), "A=\"one\"\nB=\"2\"\n");
+
+        let mut config = parsed("# comment");
+        config.set("A", "");
+        assert_eq!(config.render(), "# comment\nA=\"\"\n");
+        assert_eq!(config.get("A"), Some(""));
     }
 
-    Ok(())
-}
\ No newline at end of file
+    #[test]
+    fn set_typed_values() {
+        let mut config = parsed("LB_BOOTAPPEND_LIVE=\"boot=live\" # append\nLB_SECURITY=true\n");
+        let values = vec!["boot=live".to_string(), "components".to_string(), "locales=de_DE.UTF-8".to_string()];
+        config.set_list("LB_BOOTAPPEND_LIVE", &values);
+        config.set_bool("LB_SECURITY", false);
+        config.set_list("LB_EMPTY", &[]);
+        assert_eq!(
+            config.render(),
+            format!("LB_BOOTAPPEND_LIVE={} # append\nLB_SECURITY=\"false\"\nLB_EMPTY=\"\"\n", quote("boot=live components locales=de_DE.UTF-8"))
+        );
+
+        let reloaded = parsed(&config.render());
+        assert_eq!(reloaded.get_list("LB_BOOTAPPEND_LIVE"), Some(values));
+        assert_eq!(reloaded.get_bool("LB_SECURITY"), Some(false));
+        assert_eq!(reloaded.get_list("LB_EMPTY"), Some(Vec::new()));
+    }
+
+    #[test]
+    fn unset() {
+        let mut config = parsed("A=1\n# comment\nB=2; A=3\nexport A=4 # last\n");
+        config.unset("A");
+        assert_eq!(config.render(), "# comment\nB=2; ");
+        assert_eq!(config.get("A"), None);
+        assert_eq!(config.get("B"), Some("2"));
+        config.unset("MISSING");
+        assert_eq!(config.keys(), ["B"]);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse("A=1\nB=\"open\n").err().unwrap(), "line 2: unterminated double quote");
+        assert_eq!(parse("A='open").err().unwrap(), "line 1: unterminated single quote");
+        assert_eq!(parse("A=$(date\n").err().unwrap(), "line 1: unterminated `(`");
+    }
+}
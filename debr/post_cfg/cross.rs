@@ -0,0 +1,124 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::sync::OnceLock;
+
+use super::lb_config::LbConfig;
+
+const BINFMT_DIR: &str = "/proc/sys/fs/binfmt_misc";
+const INSTALL_HINT: &str = "sudo apt-get install qemu-user-static binfmt-support";
+
+// what live-build needs to know per architecture
+struct Architecture {
+    name: &'static str,
+    qemu: &'static str,
+    linux_flavour: &'static str,
+    bootloader_bios: &'static str,
+    bootloader_efi: &'static str,
+}
+
+const ARCHITECTURES: [Architecture; 7] = [
+    Architecture { name: "amd64", qemu: "x86_64", linux_flavour: "amd64", bootloader_bios: "syslinux", bootloader_efi: "grub-efi" },
+    Architecture { name: "i386", qemu: "i386", linux_flavour: "686-pae", bootloader_bios: "syslinux", bootloader_efi: "grub-efi" },
+    Architecture { name: "arm64", qemu: "aarch64", linux_flavour: "arm64", bootloader_bios: "none", bootloader_efi: "grub-efi" },
+    Architecture { name: "armhf", qemu: "arm", linux_flavour: "armmp", bootloader_bios: "none", bootloader_efi: "none" },
+    Architecture { name: "ppc64el", qemu: "ppc64le", linux_flavour: "powerpc64le", bootloader_bios: "none", bootloader_efi: "none" },
+    Architecture { name: "riscv64", qemu: "riscv64", linux_flavour: "riscv64", bootloader_bios: "none", bootloader_efi: "grub-efi" },
+    Architecture { name: "s390x", qemu: "s390x", linux_flavour: "s390x", bootloader_bios: "none", bootloader_efi: "none" },
+];
+
+fn architecture(name: &str) -> Result<&'static Architecture, Box<dyn Error>> {
+    ARCHITECTURES.iter().find(|a| a.name == name).ok_or_else(|| format!(
+        "Unsupported architecture `{}` (supported: {})",
+        name, ARCHITECTURES.iter().map(|a| a.name).collect::<Vec<&str>>().join(", ")
+    ).into())
+}
+
+// debian architecture of the build host, asked once
+fn host() -> &'static str {
+    static HOST: OnceLock<String> = OnceLock::new();
+    HOST.get_or_init(|| {
+        if let Ok(output) = Command::new("dpkg").arg("--print-architecture").output() {
+            if output.status.success() {
+                return String::from_utf8_lossy(&output.stdout).trim().to_string();
+            }
+        }
+        let name = match std::env::consts::ARCH {
+            "x86_64" => "amd64",
+            "x86" => "i386",
+            "aarch64" => "arm64",
+            "arm" => "armhf",
+            "powerpc64" => "ppc64el",
+            other => other,
+        };
+        name.to_string()
+    })
+}
+
+// whether the host runs binaries of the architecture without emulation
+fn is_native(name: &str) -> bool {
+    let host = host();
+    name == host || (host == "amd64" && name == "i386")
+}
+
+// sets the architecture with its kernel flavour and bootloaders, and emulation by qemu-user if the host can't run it
+pub fn apply(name: &str, lb_config: &mut LbConfig) -> Result<(), Box<dyn Error>> {
+    let architecture = architecture(name)?;
+    lb_config.bootstrap.architecture = name.to_string();
+    lb_config.chroot.linux_flavours = vec![architecture.linux_flavour.to_string()];
+    lb_config.binary.bootloader_bios = architecture.bootloader_bios.to_string();
+    lb_config.binary.bootloader_efi = architecture.bootloader_efi.to_string();
+    // live-build runs the second stage of debootstrap with the static qemu copied into the chroot
+    if is_native(name) {
+        lb_config.common.bootstrap_qemu_architecture = String::new();
+        lb_config.common.bootstrap_qemu_static = String::new();
+    } else {
+        lb_config.common.bootstrap_qemu_architecture = name.to_string();
+        lb_config.common.bootstrap_qemu_static = format!("/usr/bin/qemu-{}-static", architecture.qemu);
+    }
+    Ok(())
+}
+
+// fails if the config needs emulation the host doesn't provide
+pub fn check(lb_config: &LbConfig) -> Result<(), Box<dyn Error>> {
+    let name = &lb_config.bootstrap.architecture;
+    if is_native(name) {
+        return Ok(());
+    }
+    let architecture = architecture(name)?;
+    let host = host();
+    let reason = format!("Building {} on {} runs the {} binaries with qemu-user", name, host, name);
+
+    let qemu_static = format!("/usr/bin/qemu-{}-static", architecture.qemu);
+    if !Path::new(&qemu_static).exists() {
+        return Err(format!("{}, but {} is missing, install it with `{}`", reason, qemu_static, INSTALL_HINT).into());
+    }
+    let status = fs::read_to_string(Path::new(BINFMT_DIR).join("status")).unwrap_or_default();
+    if status.trim() != "enabled" {
+        return Err(format!(
+            "{}, but binfmt_misc isn't available, mount it with `sudo mount -t binfmt_misc binfmt_misc {}`",
+            reason, BINFMT_DIR
+        ).into());
+    }
+    let handler_name = format!("qemu-{}", architecture.qemu);
+    let Ok(handler) = fs::read_to_string(Path::new(BINFMT_DIR).join(&handler_name)) else {
+        return Err(format!(
+            "{}, but no binfmt_misc handler {} is registered, install `{}` or run `sudo update-binfmts --enable {}`",
+            reason, handler_name, INSTALL_HINT, handler_name
+        ).into());
+    };
+    if handler.lines().next() != Some("enabled") {
+        return Err(format!("{}, but the binfmt_misc handler {} is disabled, run `sudo update-binfmts --enable {}`", reason, handler_name, handler_name).into());
+    }
+    // the interpreter has to exist inside the chroot, unless the kernel keeps it open (`F` flag)
+    let interpreter = handler.lines().find_map(|line| line.strip_prefix("interpreter ")).unwrap_or_default();
+    let flags = handler.lines().find_map(|line| line.strip_prefix("flags: ")).unwrap_or_default();
+    if interpreter != qemu_static && !flags.contains('F') {
+        return Err(format!(
+            "{}, but the binfmt_misc handler {} uses {} without the `F` flag, which isn't available inside the chroot, install `{}`",
+            reason, handler_name, interpreter, INSTALL_HINT
+        ).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,129 @@
+use std::error::Error;
+use std::fs::{create_dir_all, write};
+use std::io;
+use std::path::Path;
+use ordermap::{OrderMap, OrderSet};
+use serde_json::Value;
+
+use super::json_cfg::GSetting;
+
+const DB_NAME: &str = "local";
+
+// system-wide dconf database, compiled in the chroot by `dconf update`
+#[derive(Default)]
+pub struct Dconf {
+    // dconf dir (e.g. "org/gnome/desktop/interface") => key => GVariant text
+    settings: OrderMap<String, OrderMap<String, String>>,
+    locks: OrderSet<String>,
+}
+
+impl Dconf {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.settings.is_empty()
+    }
+
+    // set a value, conflicting values for the same key are an error
+    pub fn set(&mut self, dir: &str, key: &str, value: &str, lock: bool) -> Result<(), Box<dyn Error>> {
+        let dir = dir.trim_matches('/');
+        let keys = self.settings.entry(dir.to_string()).or_default();
+        if let Some(existing) = keys.get(key) {
+            if existing != value {
+                return Err(format!(
+                    "Conflict in `gsettings` for /{}/{}\nThisValue:\n{}\nOtherValue:\n{}",
+                    dir, key, existing, value
+                ).into());
+            }
+        }
+        keys.insert(key.to_string(), value.to_string());
+        if lock {
+            self.locks.insert(format!("/{}/{}", dir, key));
+        }
+        Ok(())
+    }
+
+    // set a value only if the config did not set the key already
+    pub fn set_default(&mut self, dir: &str, key: &str, value: &str) {
+        let keys = self.settings.entry(dir.trim_matches('/').to_string()).or_default();
+        if !keys.contains_key(key) {
+            keys.insert(key.to_string(), value.to_string());
+        }
+    }
+
+    pub fn extend(&mut self, gsettings: &[GSetting]) -> Result<(), Box<dyn Error>> {
+        for setting in gsettings {
+            let dir = match &setting.path {
+                Some(path) => path.clone(),
+                None => schema_to_dir(&setting.schema),
+            };
+            let value = if setting.raw.unwrap_or(false) {
+                match &setting.value {
+                    Value::String(raw) => raw.clone(),
+                    other => return Err(format!("`raw` gsettings value for {}.{} has to be a string, got {}", setting.schema, setting.key, other).into()),
+                }
+            } else {
+                to_gvariant(&setting.value).map_err(|e| format!("{}.{}: {}", setting.schema, setting.key, e))?
+            };
+            self.set(&dir, &setting.key, &value, setting.lock.unwrap_or(false))?;
+        }
+        Ok(())
+    }
+
+    // keyfile for /etc/dconf/db/local.d/
+    pub fn keyfile(&self) -> String {
+        let mut content = String::new();
+        for (dir, keys) in &self.settings {
+            if keys.is_empty() {
+                continue;
+            }
+            content.push_str(&format!("[{}]\n", dir));
+            for (key, value) in keys {
+                content.push_str(&format!("{}={}\n", key, value));
+            }
+            content.push('\n');
+        }
+        content
+    }
+
+    // writes profile, keyfile and locks below the chroot includes directory
+    pub fn place(&self, includes_dir: &Path) -> io::Result<()> {
+        let profile_path = includes_dir.join("etc/dconf/profile/user");
+        let db_dir = includes_dir.join(format!("etc/dconf/db/{}.d", DB_NAME));
+        create_dir_all(profile_path.parent().unwrap())?;
+        create_dir_all(&db_dir)?;
+        write(&profile_path, format!("user-db:user\nsystem-db:{}\n", DB_NAME))?;
+        write(db_dir.join("00-debr"), self.keyfile())?;
+
+        if !self.locks.is_empty() {
+            let locks_dir = db_dir.join("locks");
+            create_dir_all(&locks_dir)?;
+            let locks = self.locks.iter().cloned().collect::<Vec<String>>().join("\n");
+            write(locks_dir.join("00-debr"), format!("{}\n", locks))?;
+        }
+        Ok(())
+    }
+}
+
+// "org.gnome.desktop.interface" => "org/gnome/desktop/interface"
+pub fn schema_to_dir(schema: &str) -> String {
+    schema.trim_matches('.').replace('.', "/")
+}
+
+// JSON value to GVariant text format
+pub fn to_gvariant(value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(s) => Ok(format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))),
+        Value::Bool(b) => Ok(b.to_string()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Array(items) if items.is_empty() => Ok("@as []".to_string()),
+        Value::Array(items) => {
+            let items = items.iter().map(to_gvariant).collect::<Result<Vec<String>, String>>()?;
+            Ok(format!("[{}]", items.join(", ")))
+        }
+        Value::Null => Err("null is not a valid gsettings value".to_string()),
+        Value::Object(_) => Err("objects are not supported, use a `raw` GVariant string instead".to_string()),
+    }
+}
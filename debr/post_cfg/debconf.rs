@@ -0,0 +1,54 @@
+use std::error::Error;
+use std::fs::{create_dir_all, write};
+use std::io;
+use std::path::Path;
+use ordermap::OrderMap;
+
+use super::json_cfg::DebconfSelection;
+
+const TYPES: [&str; 9] = ["string", "boolean", "select", "multiselect", "note", "text", "password", "title", "error"];
+
+// renders the selections in the format of debconf-set-selections
+pub fn render(selections: &[DebconfSelection]) -> Result<String, Box<dyn Error>> {
+    // question => line
+    let mut lines: OrderMap<&str, String> = OrderMap::new();
+
+    for selection in selections {
+        let question = selection.question.as_str();
+        if selection.package.is_empty() || selection.package.contains(char::is_whitespace) {
+            return Err(format!("Invalid package `{}` for debconf question `{}`", selection.package, question).into());
+        }
+        if question.is_empty() || !question.contains('/') || question.contains(char::is_whitespace) {
+            return Err(format!("Invalid debconf question `{}`, expected `<template>/<name>`", question).into());
+        }
+        if !TYPES.contains(&selection.kind.as_str()) {
+            return Err(format!(
+                "Invalid type `{}` for debconf question `{}` (Allowed values: {})",
+                selection.kind, question,
+                TYPES.iter().map(|t| format!("`{}`", t)).collect::<Vec<String>>().join(", ")
+            ).into());
+        }
+        if selection.value.contains('\n') {
+            return Err(format!("Value for debconf question `{}` contains a newline", question).into());
+        }
+
+        let line = format!("{} {} {} {}", selection.package, question, selection.kind, selection.value);
+        if let Some(existing) = lines.get(question) {
+            if existing != &line {
+                return Err(format!("Conflict in `debconf` for `{}`\nThisValue:\n{}\nOtherValue:\n{}", question, existing, line).into());
+            }
+        }
+        lines.insert(question, line);
+    }
+
+    let mut content = lines.into_values().collect::<Vec<String>>().join("\n");
+    content.push('\n');
+    Ok(content)
+}
+
+// live-build feeds config/preseed/*.preseed.chroot to debconf-set-selections before installing packages
+pub fn place(content: &str, live_dir: &Path) -> io::Result<()> {
+    let preseed_dir = live_dir.join("config/preseed");
+    create_dir_all(&preseed_dir)?;
+    write(preseed_dir.join("debr.preseed.chroot"), content)
+}
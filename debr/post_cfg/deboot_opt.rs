@@ -1,24 +1,224 @@
-use std::collections::HashSet;
-
-pub fn parse(command: &String, include_extras: Vec<String>) -> String {
-    let mut extras_set: HashSet<&str> = include_extras.iter().map(|s| s.as_str()).collect();
-
-    if let Some(include_start) = command.find("--include=") {
-        let include_end = command[include_start..].find(' ').unwrap_or(command.len());
-        let existing_values = &command[include_start + 10..include_start + include_end];
-        
-        existing_values.split(',').for_each(|value| { extras_set.insert(value); });
-
-        let combined_values = extras_set.into_iter().collect::<Vec<&str>>().join(",");
-
-        format!(
-            "{}--include={}{}",
-            &command[..include_start],
-            combined_values,
-            &command[include_start + include_end..]
-        )
-    } else {
-        let unique_values = extras_set.into_iter().collect::<Vec<&str>>().join(",");
-        format!("{} --include={}", command, unique_values)
-    }
-}
\ No newline at end of file
+use std::error::Error;
+
+use super::json_cfg::Debootstrap;
+
+// debootstrap options, live-build passes DEBOOTSTRAP_OPTIONS word-split but without quote removal
+#[derive(Debug, Default, PartialEq)]
+pub struct DebootOpts {
+    pub variant: Option<String>,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub components: Vec<String>,
+    pub keyring: Option<String>,
+    // any other arguments, kept in order
+    pub other: Vec<String>,
+}
+
+impl DebootOpts {
+    // argv-style, `--opt=value` or `--opt value`, single and double quotes are removed
+    pub fn parse(options: &str) -> Result<Self, Box<dyn Error>> {
+        let args = split(options)?;
+        let mut opts = DebootOpts::default();
+        let mut i = 0;
+        while i < args.len() {
+            let arg = &args[i];
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => (name, Some(value.to_string())),
+                _ => (arg.as_str(), None),
+            };
+            if !["--variant", "--include", "--exclude", "--components", "--keyring"].contains(&name) {
+                opts.other.push(arg.clone());
+                i += 1;
+                continue;
+            }
+            let value = match inline {
+                Some(value) => value,
+                None => {
+                    i += 1;
+                    args.get(i).cloned().ok_or(format!("Missing value for `{}` in debootstrap options", name))?
+                }
+            };
+            match name {
+                "--variant" => opts.set_variant(&value)?,
+                "--keyring" => opts.set_keyring(&value)?,
+                "--include" => opts.include(value.split(',')),
+                "--exclude" => opts.exclude(value.split(',')),
+                _ => extend_unique(&mut opts.components, value.split(',')),
+            }
+            i += 1;
+        }
+        Ok(opts)
+    }
+
+    pub fn apply(&mut self, debootstrap: &Debootstrap) -> Result<(), Box<dyn Error>> {
+        if let Some(variant) = &debootstrap.variant {
+            self.set_variant(variant)?;
+        }
+        if let Some(keyring) = &debootstrap.keyring {
+            self.set_keyring(keyring)?;
+        }
+        self.include(debootstrap.include.iter().flatten());
+        self.exclude(debootstrap.exclude.iter().flatten());
+        extend_unique(&mut self.components, debootstrap.components.iter().flatten());
+        Ok(())
+    }
+
+    pub fn include<S: AsRef<str>>(&mut self, packages: impl IntoIterator<Item = S>) {
+        extend_unique(&mut self.include, packages);
+    }
+
+    pub fn exclude<S: AsRef<str>>(&mut self, packages: impl IntoIterator<Item = S>) {
+        extend_unique(&mut self.exclude, packages);
+    }
+
+    fn set_variant(&mut self, variant: &str) -> Result<(), Box<dyn Error>> {
+        set_once(&mut self.variant, variant, "variant")
+    }
+
+    fn set_keyring(&mut self, keyring: &str) -> Result<(), Box<dyn Error>> {
+        set_once(&mut self.keyring, keyring, "keyring")
+    }
+
+    pub fn render(&self) -> Result<String, Box<dyn Error>> {
+        if let Some(package) = self.include.iter().find(|p| self.exclude.contains(p)) {
+            return Err(format!("`{}` is included and excluded in the debootstrap options", package).into());
+        }
+        let mut args: Vec<String> = Vec::new();
+        if let Some(variant) = &self.variant {
+            args.push(format!("--variant={}", variant));
+        }
+        for (name, values) in [("include", &self.include), ("exclude", &self.exclude), ("components", &self.components)] {
+            if !values.is_empty() {
+                args.push(format!("--{}={}", name, values.join(",")));
+            }
+        }
+        if let Some(keyring) = &self.keyring {
+            args.push(format!("--keyring={}", keyring));
+        }
+        args.extend(self.other.iter().cloned());
+        let quoted: Vec<String> = args.iter().map(|arg| quote(arg)).collect();
+        if let Some(arg) = args.iter().zip(&quoted).find_map(|(arg, quoted)| (arg != quoted).then_some(quoted)) {
+            eprintln!("W: debootstrap option {} is quoted, live-build passes the quotes on to debootstrap", arg);
+        }
+        Ok(quoted.join(" "))
+    }
+}
+
+fn set_once(field: &mut Option<String>, value: &str, name: &str) -> Result<(), Box<dyn Error>> {
+    match field {
+        Some(existing) if existing != value => {
+            Err(format!("Conflict in debootstrap `{}`\nThisValue:\n{}\nOtherValue:\n{}", name, existing, value).into())
+        }
+        _ => {
+            *field = Some(value.to_string());
+            Ok(())
+        }
+    }
+}
+
+fn extend_unique<S: AsRef<str>>(values: &mut Vec<String>, new: impl IntoIterator<Item = S>) {
+    for value in new {
+        let value = value.as_ref().trim();
+        if !value.is_empty() && !values.iter().any(|v| v == value) {
+            values.push(value.to_string());
+        }
+    }
+}
+
+// single quoted if it contains whitespace, quotes or backslashes, only the value of `--opt=value`,
+// `split` reads it back
+fn quote(arg: &str) -> String {
+    let needs_quotes = |value: &str| value.is_empty() || value.contains(|c: char| c.is_whitespace() || "\"'\\".contains(c));
+    if !needs_quotes(arg) {
+        return arg.to_string();
+    }
+    match arg.split_once('=') {
+        Some((name, value)) if name.starts_with("--") && !needs_quotes(name) => format!("{}={}", name, quote(value)),
+        _ => format!("'{}'", arg.replace('\'', "'\\''")),
+    }
+}
+
+// splits on whitespace outside of quotes, removing the quotes
+fn split(options: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut quote: Option<char> = None;
+    let mut chars = options.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') => current.extend(chars.next()),
+            (Some(_), c) => current.push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                in_arg = true;
+            }
+            (None, '\\') => {
+                current.extend(chars.next());
+                in_arg = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            (None, c) => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if quote.is_some() {
+        return Err(format!("Unterminated quote in debootstrap options `{}`", options).into());
+    }
+    if in_arg {
+        args.push(current);
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn split_quotes() {
+        assert_eq!(split("  a 'b c'  \"d \\\" e\" f\\ g h'i'j ''").unwrap(), strings(&["a", "b c", "d \" e", "f g", "hij", ""]));
+        assert_eq!(split("").unwrap(), Vec::<String>::new());
+        assert!(split("--keyring='open").is_err());
+    }
+
+    #[test]
+    fn parse() {
+        let opts = DebootOpts::parse("--include=a,b --include c --exclude=d --variant minbase --components=main,contrib --keyring '/etc/my keys.gpg' --no-merged-usr").unwrap();
+        assert_eq!(opts.include, strings(&["a", "b", "c"]));
+        assert_eq!(opts.exclude, strings(&["d"]));
+        assert_eq!(opts.variant.as_deref(), Some("minbase"));
+        assert_eq!(opts.components, strings(&["main", "contrib"]));
+        assert_eq!(opts.keyring.as_deref(), Some("/etc/my keys.gpg"));
+        assert_eq!(opts.other, strings(&["--no-merged-usr"]));
+
+        assert!(DebootOpts::parse("--variant=minbase --variant=buildd").is_err());
+        assert!(DebootOpts::parse("--include").is_err());
+    }
+
+    #[test]
+    fn render_round_trip() {
+        for options in [
+            "--variant=minbase --include=a,b --exclude=c --components=main --keyring=/etc/keys.gpg --no-merged-usr",
+            "--keyring='/etc/my keys.gpg' --extra-suites='a b' --opt='it'\\''s' --empty= ''",
+        ] {
+            let opts = DebootOpts::parse(options).unwrap();
+            assert_eq!(opts.render().unwrap(), options);
+            assert_eq!(DebootOpts::parse(&opts.render().unwrap()).unwrap(), opts);
+        }
+        let opts = DebootOpts::parse("--include a --include=\"b\" --exclude a").unwrap();
+        assert!(opts.render().is_err());
+    }
+}
@@ -0,0 +1,77 @@
+use std::error::Error;
+use std::fs::{copy, create_dir_all, set_permissions};
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use super::dconf::Dconf;
+use super::hooks;
+
+mod gnome;
+mod kde;
+mod xfce;
+
+// settings applied through the desktop environment of the image
+pub trait Desktop {
+    fn name(&self) -> &'static str;
+
+    fn dark_mode(&mut self) -> Result<(), Box<dyn Error>> {
+        Err(unsupported(self.name(), "darkMode"))
+    }
+
+    fn theme(&mut self, _theme: &str) -> Result<(), Box<dyn Error>> {
+        Err(unsupported(self.name(), "theme"))
+    }
+
+    fn favorite_apps(&mut self, _apps: &[String]) -> Result<(), Box<dyn Error>> {
+        Err(unsupported(self.name(), "favoriteApps"))
+    }
+
+    fn wallpaper(&mut self, _path: &str) -> Result<(), Box<dyn Error>> {
+        Err(unsupported(self.name(), "wallpaper"))
+    }
+
+    fn autostart(&mut self, _apps: &[String]) -> Result<(), Box<dyn Error>> {
+        Err(unsupported(self.name(), "autostart"))
+    }
+
+    // write the collected settings into the config tree
+    fn place(&self, includes_dir: &Path, live_dir: &Path, dconf: &mut Dconf) -> Result<(), Box<dyn Error>>;
+}
+
+pub fn select(name: &str) -> Result<Box<dyn Desktop>, Box<dyn Error>> {
+    match name {
+        "gnome" => Ok(Box::new(gnome::Gnome::default())),
+        "xfce" => Ok(Box::new(xfce::Xfce::default())),
+        "kde" => Ok(Box::new(kde::Kde::default())),
+        other => Err(format!("Unknown desktop `{}` (Allowed values: `gnome`, `xfce`, `kde`)", other).into()),
+    }
+}
+
+fn unsupported(desktop: &str, setting: &str) -> Box<dyn Error> {
+    format!("`{}` is not supported for desktop `{}`", setting, desktop).into()
+}
+
+// copies a wallpaper from the build host into the image, paths not present on the host are expected to exist in the image
+pub fn resolve_wallpaper(path: &str, includes_dir: &Path) -> io::Result<String> {
+    let host_path = Path::new(path);
+    if !host_path.is_file() {
+        return Ok(path.to_string());
+    }
+    let file_name = host_path.file_name().unwrap().to_string_lossy().to_string();
+    let image_path = format!("/usr/share/backgrounds/debr/{}", file_name);
+    let target = includes_dir.join(image_path.trim_start_matches('/'));
+    create_dir_all(target.parent().unwrap())?;
+    copy(host_path, &target)?;
+    set_permissions(&target, PermissionsExt::from_mode(0o644))?;
+    Ok(image_path)
+}
+
+// XDG autostart is shared by all supported desktops
+fn place_autostart(apps: &[String], live_dir: &Path) -> io::Result<()> {
+    if apps.is_empty() {
+        return Ok(());
+    }
+    let content = hooks::xdg_autostart(apps)?;
+    hooks::add_hook("0530-xdg-autostart.hook.chroot", &content, live_dir, false)
+}
@@ -0,0 +1,70 @@
+use std::error::Error;
+use std::path::Path;
+
+use super::{place_autostart, Desktop};
+use crate::post_cfg::dconf::{to_gvariant, Dconf};
+
+const INTERFACE: &str = "org/gnome/desktop/interface";
+const BACKGROUND: &str = "org/gnome/desktop/background";
+
+#[derive(Default)]
+pub struct Gnome {
+    dark: bool,
+    theme: Option<String>,
+    favorite_apps: Option<Vec<String>>,
+    wallpaper: Option<String>,
+    autostart: Vec<String>,
+}
+
+impl Desktop for Gnome {
+    fn name(&self) -> &'static str {
+        "gnome"
+    }
+
+    fn dark_mode(&mut self) -> Result<(), Box<dyn Error>> {
+        self.dark = true;
+        Ok(())
+    }
+
+    fn theme(&mut self, theme: &str) -> Result<(), Box<dyn Error>> {
+        self.theme = Some(theme.to_string());
+        Ok(())
+    }
+
+    fn favorite_apps(&mut self, apps: &[String]) -> Result<(), Box<dyn Error>> {
+        self.favorite_apps = Some(apps.to_vec());
+        Ok(())
+    }
+
+    fn wallpaper(&mut self, path: &str) -> Result<(), Box<dyn Error>> {
+        self.wallpaper = Some(path.to_string());
+        Ok(())
+    }
+
+    fn autostart(&mut self, apps: &[String]) -> Result<(), Box<dyn Error>> {
+        self.autostart.extend_from_slice(apps);
+        Ok(())
+    }
+
+    fn place(&self, _includes_dir: &Path, live_dir: &Path, dconf: &mut Dconf) -> Result<(), Box<dyn Error>> {
+        // explicit settings conflict with differing `gsettings`, darkMode only fills in defaults
+        if let Some(theme) = &self.theme {
+            dconf.set(INTERFACE, "gtk-theme", &to_gvariant(&theme.as_str().into())?, false)?;
+        }
+        if let Some(apps) = &self.favorite_apps {
+            dconf.set("org/gnome/shell", "favorite-apps", &to_gvariant(&apps.clone().into())?, false)?;
+        }
+        if let Some(wallpaper) = &self.wallpaper {
+            let uri = to_gvariant(&format!("file://{}", wallpaper).into())?;
+            dconf.set(BACKGROUND, "picture-uri", &uri, false)?;
+            dconf.set(BACKGROUND, "picture-uri-dark", &uri, false)?;
+        }
+        if self.dark {
+            dconf.set_default(INTERFACE, "color-scheme", "'prefer-dark'");
+            dconf.set_default(INTERFACE, "gtk-theme", "'Adwaita-dark'");
+        }
+
+        place_autostart(&self.autostart, live_dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,65 @@
+use std::error::Error;
+use std::fs::{create_dir_all, write};
+use std::path::Path;
+use ordermap::OrderMap;
+
+use super::{place_autostart, Desktop};
+use crate::post_cfg::dconf::Dconf;
+
+#[derive(Default)]
+pub struct Kde {
+    dark: bool,
+    theme: Option<String>,
+    autostart: Vec<String>,
+}
+
+impl Desktop for Kde {
+    fn name(&self) -> &'static str {
+        "kde"
+    }
+
+    fn dark_mode(&mut self) -> Result<(), Box<dyn Error>> {
+        self.dark = true;
+        Ok(())
+    }
+
+    // a color scheme name, e.g. `BreezeDark`
+    fn theme(&mut self, theme: &str) -> Result<(), Box<dyn Error>> {
+        self.theme = Some(theme.to_string());
+        Ok(())
+    }
+
+    fn autostart(&mut self, apps: &[String]) -> Result<(), Box<dyn Error>> {
+        self.autostart.extend_from_slice(apps);
+        Ok(())
+    }
+
+    fn place(&self, includes_dir: &Path, live_dir: &Path, _dconf: &mut Dconf) -> Result<(), Box<dyn Error>> {
+        // section => key => value of /etc/xdg/kdeglobals
+        let mut globals: OrderMap<&str, OrderMap<&str, String>> = OrderMap::new();
+        if self.dark {
+            globals.entry("KDE").or_default().insert("LookAndFeelPackage", "org.kde.breezedark.desktop".to_string());
+            globals.entry("General").or_default().insert("ColorScheme", "BreezeDark".to_string());
+        }
+        if let Some(theme) = &self.theme {
+            globals.entry("General").or_default().insert("ColorScheme", theme.clone());
+        }
+
+        if !globals.is_empty() {
+            let mut content = String::new();
+            for (section, keys) in &globals {
+                content.push_str(&format!("[{}]\n", section));
+                for (key, value) in keys {
+                    content.push_str(&format!("{}={}\n", key, value));
+                }
+                content.push('\n');
+            }
+            let path = includes_dir.join("etc/xdg/kdeglobals");
+            create_dir_all(path.parent().unwrap())?;
+            write(&path, content)?;
+        }
+
+        place_autostart(&self.autostart, live_dir)?;
+        Ok(())
+    }
+}
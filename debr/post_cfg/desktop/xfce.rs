@@ -0,0 +1,132 @@
+use std::error::Error;
+use std::fs::{create_dir_all, write};
+use std::io;
+use std::path::Path;
+use ordermap::OrderMap;
+
+use super::{place_autostart, Desktop};
+use crate::post_cfg::dconf::Dconf;
+
+const XFCONF_DIR: &str = "etc/xdg/xfce4/xfconf/xfce-perchannel-xml";
+
+#[derive(Default)]
+pub struct Xfce {
+    dark: bool,
+    theme: Option<String>,
+    wallpaper: Option<String>,
+    autostart: Vec<String>,
+}
+
+impl Desktop for Xfce {
+    fn name(&self) -> &'static str {
+        "xfce"
+    }
+
+    fn dark_mode(&mut self) -> Result<(), Box<dyn Error>> {
+        self.dark = true;
+        Ok(())
+    }
+
+    fn theme(&mut self, theme: &str) -> Result<(), Box<dyn Error>> {
+        self.theme = Some(theme.to_string());
+        Ok(())
+    }
+
+    fn wallpaper(&mut self, path: &str) -> Result<(), Box<dyn Error>> {
+        self.wallpaper = Some(path.to_string());
+        Ok(())
+    }
+
+    fn autostart(&mut self, apps: &[String]) -> Result<(), Box<dyn Error>> {
+        self.autostart.extend_from_slice(apps);
+        Ok(())
+    }
+
+    fn place(&self, includes_dir: &Path, live_dir: &Path, _dconf: &mut Dconf) -> Result<(), Box<dyn Error>> {
+        let xfconf_dir = includes_dir.join(XFCONF_DIR);
+
+        let theme = match (&self.theme, self.dark) {
+            (Some(theme), _) => Some(theme.as_str()),
+            (None, true) => Some("Adwaita-dark"),
+            (None, false) => None,
+        };
+        if let Some(theme) = theme {
+            let mut xsettings = Channel::default();
+            xsettings.set("/Net/ThemeName", "string", theme);
+            xsettings.place("xsettings", &xfconf_dir)?;
+        }
+
+        if let Some(wallpaper) = &self.wallpaper {
+            let mut desktop = Channel::default();
+            desktop.set("/backdrop/screen0/monitor0/workspace0/last-image", "string", wallpaper);
+            desktop.set("/backdrop/screen0/monitor0/workspace0/image-style", "int", "5");
+            desktop.place("xfce4-desktop", &xfconf_dir)?;
+        }
+
+        place_autostart(&self.autostart, live_dir)?;
+        Ok(())
+    }
+}
+
+// xfconf channel, properties are nested by their path segments
+#[derive(Default)]
+struct Channel {
+    properties: OrderMap<String, Property>,
+}
+
+#[derive(Default)]
+struct Property {
+    value: Option<(String, String)>,
+    children: OrderMap<String, Property>,
+}
+
+impl Channel {
+    fn set(&mut self, path: &str, kind: &str, value: &str) {
+        let mut segments = path.trim_matches('/').split('/');
+        let first = segments.next().unwrap_or_default();
+        let mut property = self.properties.entry(first.to_string()).or_default();
+        for segment in segments {
+            property = property.children.entry(segment.to_string()).or_default();
+        }
+        property.value = Some((kind.to_string(), value.to_string()));
+    }
+
+    fn place(&self, name: &str, xfconf_dir: &Path) -> io::Result<()> {
+        let mut content = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\n");
+        content.push_str(&format!("<channel name=\"{}\" version=\"1.0\">\n", escape(name)));
+        for (name, property) in &self.properties {
+            property.render(name, 1, &mut content);
+        }
+        content.push_str("</channel>\n");
+
+        create_dir_all(xfconf_dir)?;
+        write(xfconf_dir.join(format!("{}.xml", name)), content)
+    }
+}
+
+impl Property {
+    fn render(&self, name: &str, depth: usize, out: &mut String) {
+        let indent = "  ".repeat(depth);
+        let (kind, value) = match &self.value {
+            Some((kind, value)) => (kind.as_str(), format!(" value=\"{}\"", escape(value))),
+            None => ("empty", String::new()),
+        };
+        if self.children.is_empty() {
+            out.push_str(&format!("{}<property name=\"{}\" type=\"{}\"{}/>\n", indent, escape(name), kind, value));
+            return;
+        }
+        out.push_str(&format!("{}<property name=\"{}\" type=\"{}\"{}>\n", indent, escape(name), kind, value));
+        for (child_name, child) in &self.children {
+            child.render(child_name, depth + 1, out);
+        }
+        out.push_str(&format!("{}</property>\n", indent));
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,164 @@
+use std::error::Error;
+use std::fs::{self, create_dir_all, set_permissions, File};
+use std::io;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::{Path, PathBuf};
+use reqwest::blocking::{Client, ClientBuilder};
+use sha2::{Digest, Sha256};
+
+use super::json_cfg::Download;
+use super::store::hex_encode;
+use crate::bash;
+
+const ARCHIVES: [&str; 7] = [".tar.gz", ".tgz", ".tar.xz", ".tar.bz2", ".tar.zst", ".tar", ".zip"];
+
+pub fn client() -> Result<Client, Box<dyn Error>> {
+    Ok(ClientBuilder::new()
+        .https_only(true)
+        .timeout(None)
+        .build()?)
+}
+
+// a single path component, so it can't leave the target directory
+fn is_file_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && name.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+}
+
+// fetches the download into the cache, verifies it and places it below the chroot includes directory
+pub fn place(download: &Download, client: &Client, cache_dir: &Path, includes_dir: &Path, offline: bool) -> Result<(), Box<dyn Error>> {
+    let name = &download.name;
+    if !is_file_name(name) {
+        return Err(format!("Invalid download name `{}`", name).into());
+    }
+    if let Some(file_name) = &download.file_name {
+        if !is_file_name(file_name) {
+            return Err(format!("Invalid `fileName` `{}` for download {}", file_name, name).into());
+        }
+    }
+    if !download.target.starts_with('/') {
+        return Err(format!("`target` of download {} has to be an absolute path, got `{}`", name, download.target).into());
+    }
+    let mode = u32::from_str_radix(download.mode.as_deref().unwrap_or("755"), 8)
+        .map_err(|_| format!("Invalid `mode` for download {}, expected octal e.g. `755`", name))?;
+
+    let cached = fetch(name, &download.url, &download.sha256, client, cache_dir, offline)?;
+    let target_dir = includes_dir.join(download.target.trim_start_matches('/'));
+    create_dir_all(&target_dir)?;
+
+    let url_name = download.url.rsplit('/').next().unwrap_or(name).split(['?', '#']).next().unwrap_or(name);
+    if download.file_name.is_none() && (url_name.is_empty() || url_name == "." || url_name == "..") {
+        return Err(format!("The URL of download {} doesn't end with a file name, set `fileName`", name).into());
+    }
+    let file_name = download.file_name.as_deref().unwrap_or(url_name);
+    // path of the executable inside the image
+    let placed = if download.extract.unwrap_or(false) {
+        extract(name, file_name, &cached, &target_dir, download.strip_components.unwrap_or(0))?;
+        match &download.bin {
+            Some(bin) => {
+                let bin = bin.trim_start_matches('/');
+                if bin.split('/').any(|segment| segment == "..") {
+                    return Err(format!("Invalid `bin` `{}` for download {}, expected a path inside the archive", bin, name).into());
+                }
+                let path = target_dir.join(bin);
+                let Ok(metadata) = path.symlink_metadata() else {
+                    return Err(format!("`bin` `{}` of download {} is not in the archive", bin, name).into());
+                };
+                // a symlink would be followed on the host
+                if download.mode.is_some() && !metadata.is_file() {
+                    return Err(format!("`mode` of download {} needs `bin` `{}` to be a regular file", name, bin).into());
+                }
+                if download.mode.is_some() {
+                    set_permissions(&path, PermissionsExt::from_mode(mode))?;
+                }
+                Some(format!("{}/{}", download.target.trim_end_matches('/'), bin))
+            }
+            None if download.mode.is_some() => {
+                return Err(format!("`mode` of the extracted download {} applies to `bin`, which is missing", name).into());
+            }
+            None => None,
+        }
+    } else {
+        let path = target_dir.join(file_name);
+        fs::copy(&cached, &path)?;
+        set_permissions(&path, PermissionsExt::from_mode(mode))?;
+        Some(format!("{}/{}", download.target.trim_end_matches('/'), file_name))
+    };
+
+    if download.symlink.unwrap_or(false) {
+        let executable = placed.ok_or_else(|| format!("`bin` is required to symlink the extracted download {}", name))?;
+        let link = includes_dir.join("usr/local/bin").join(name);
+        create_dir_all(link.parent().unwrap())?;
+        fs::remove_file(&link).ok();
+        symlink(&executable, &link)?;
+    }
+    println!("Placed download: {} in {}", name, download.target);
+    Ok(())
+}
+
+// downloads to `<cache_dir>/<sha256>` unless already cached, checksum mismatches are an error
+fn fetch(name: &str, url: &str, sha256: &str, client: &Client, cache_dir: &Path, offline: bool) -> Result<PathBuf, Box<dyn Error>> {
+    let sha256 = sha256.to_lowercase();
+    if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Invalid `sha256` for download {}", name).into());
+    }
+    create_dir_all(cache_dir)?;
+    let path = cache_dir.join(&sha256);
+    if path.exists() && file_sha256(&path)? == sha256 {
+        println!("Using cached download: {}", name);
+        return Ok(path);
+    }
+    if offline {
+        return Err(format!("Download {} is not cached, run `debr fetch` first", name).into());
+    }
+
+    if !url.starts_with("https://") {
+        return Err(format!("https is supported only, url: {}", url).into());
+    }
+    println!("Downloading {} from {}", name, url);
+    let mut response = client.get(url).send()?;
+    if !response.status().is_success() {
+        return Err(format!("Failed to download {}: {}", url, response.status()).into());
+    }
+    let partial = path.with_extension("part");
+    let mut file = File::create(&partial)?;
+    io::copy(&mut response, &mut file)?;
+
+    let digest = file_sha256(&partial)?;
+    if digest != sha256 {
+        fs::remove_file(&partial).ok();
+        return Err(format!("sha256 mismatch for download {}: expected {}, got {}", name, sha256, digest).into());
+    }
+    fs::rename(&partial, &path)?;
+    Ok(path)
+}
+
+fn extract(name: &str, file_name: &str, archive: &Path, target_dir: &Path, strip_components: u32) -> Result<(), Box<dyn Error>> {
+    let archive_str = archive.to_string_lossy().to_string();
+    let target_str = target_dir.to_string_lossy().to_string();
+    match ARCHIVES.iter().find(|suffix| file_name.ends_with(*suffix)) {
+        Some(&".zip") => {
+            if strip_components != 0 {
+                return Err(format!("`stripComponents` is not supported for zip archives (download {})", name).into());
+            }
+            bash::cmd("unzip", ["-q", "-o", archive_str.as_str(), "-d", target_str.as_str()], None)?;
+        }
+        Some(_) => {
+            let strip = format!("--strip-components={}", strip_components);
+            bash::cmd("tar", ["-xf", archive_str.as_str(), "-C", target_str.as_str(), strip.as_str()], None)?;
+        }
+        None => {
+            return Err(format!(
+                "Unknown archive type of download {} (supported: {})",
+                name, ARCHIVES.join(", ")
+            ).into());
+        }
+    }
+    Ok(())
+}
+
+pub fn file_sha256(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(hex_encode(&hasher.finalize()))
+}
@@ -0,0 +1,102 @@
+use std::error::Error;
+use std::io;
+use std::path::Path;
+
+use colored::*;
+use sha2::{Digest, Sha256};
+
+use super::json_cfg::{self, Config};
+use super::lb_config::{self, LbConfig};
+use super::staging::{self, Entry};
+use super::store::hex_encode;
+use crate::Args;
+
+// a change of the config tree since `debr config` generated it
+pub enum Drift {
+    // no manifest, so what debr generated is unknown
+    Unknown,
+    // the config file, debr or SOURCE_DATE_EPOCH changed
+    Inputs,
+    Variable { variable: String, current: Option<String>, expected: Option<String> },
+    Missing(String),
+    Changed(String),
+    // added by hand, `debr config` keeps it
+    Kept(String),
+}
+
+impl Drift {
+    // files added by hand aren't drift, they survive `debr config`
+    pub fn is_kept(&self) -> bool {
+        matches!(self, Drift::Kept(_))
+    }
+}
+
+// what the generated config depends on besides files the config references, hashed
+pub fn fingerprint(config: &Config, args: &Args) -> Result<String, Box<dyn Error>> {
+    let mut hasher = Sha256::new();
+    hasher.update(serde_json::to_vec(config)?);
+    hasher.update(format!(
+        "\n{}\n{}\n{}\n",
+        env!("CARGO_PKG_VERSION"),
+        std::env::var("SOURCE_DATE_EPOCH").unwrap_or_default(),
+        args.offline
+    ));
+    Ok(hex_encode(&hasher.finalize()))
+}
+
+// compares `live_dir` to the manifest `debr config` wrote, without generating the config again
+pub fn detect(args: &Args, live_dir: &Path) -> Result<Vec<Drift>, Box<dyn Error>> {
+    staging::recover(live_dir)?;
+    let Some(manifest) = staging::read_manifest(live_dir)? else {
+        return Ok(vec![Drift::Unknown]);
+    };
+
+    let mut drift = Vec::new();
+    let config = json_cfg::read_config(Path::new(&args.config))?;
+    if fingerprint(&config, args)? != manifest.inputs {
+        drift.push(Drift::Inputs);
+    }
+
+    let expected = LbConfig::from_values(&manifest.variables)?;
+    for change in LbConfig::load(live_dir)?.diff(&expected) {
+        drift.push(Drift::Variable { variable: change.variable, current: change.this, expected: change.other });
+    }
+
+    // package lists, hooks, includes, archives, preseeds, ..., the variable files are compared above
+    for (path, expected) in &manifest.files {
+        if lb_config::FILES.iter().any(|file| path.strip_prefix("config/") == Some(file)) {
+            continue;
+        }
+        match Entry::read(&live_dir.join(path)) {
+            Ok(entry) if entry != *expected => drift.push(Drift::Changed(path.clone())),
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => drift.push(Drift::Missing(path.clone())),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    for path in staging::paths(live_dir)? {
+        if path.starts_with("config/") && !manifest.files.contains_key(&path) {
+            drift.push(Drift::Kept(path));
+        }
+    }
+    Ok(drift)
+}
+
+pub fn print(drift: &[Drift]) {
+    for entry in drift {
+        let line = match entry {
+            Drift::Unknown => "? no .debr-manifest, run `debr config` to regenerate the config".red(),
+            Drift::Inputs => "~ the config changed since the last `debr config`".yellow(),
+            Drift::Variable { variable, current, expected } => format!(
+                "~ {}: {} -> {}",
+                variable,
+                current.as_ref().map_or(String::from("(unset)"), |v| format!("\"{}\"", v)),
+                expected.as_ref().map_or(String::from("(unset)"), |v| format!("\"{}\"", v)),
+            ).yellow(),
+            Drift::Missing(path) => format!("+ {} (missing)", path).green(),
+            Drift::Changed(path) => format!("~ {} (modified)", path).yellow(),
+            Drift::Kept(path) => format!("  {} (not generated by debr config, kept)", path).normal(),
+        };
+        println!("{}", line);
+    }
+}
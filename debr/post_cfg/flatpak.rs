@@ -0,0 +1,195 @@
+use std::error::Error;
+use std::fs::{self, create_dir_all, remove_dir_all};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use ordermap::OrderSet;
+use reqwest::blocking::ClientBuilder;
+
+use super::json_cfg::{FlatpakRef, FlatpakRemote, Flatpaks};
+use super::s;
+use crate::bash;
+
+// path of the prefetched repo and keys inside the chroot, removed after installing
+pub const CHROOT_REPO: &str = "/tmp/debr-flatpak/repo";
+pub const CHROOT_KEYS: &str = "/tmp/debr-flatpak/keys";
+
+// a remote with its resolved url and key
+pub struct Remote {
+    pub name: String,
+    pub url: String,
+    pub has_key: bool,
+}
+
+// remotes and the requested (remote, ref) pairs to install
+pub struct Prefetched {
+    pub remotes: Vec<Remote>,
+    pub refs: Vec<(String, String)>,
+}
+
+// prefetches the refs and their runtimes into `<cache_dir>/repo` and places the needed subset into the chroot includes,
+// `offline` takes them from the cache repo only
+pub fn prefetch(flatpaks: &Flatpaks, architecture: &str, cache_dir: &Path, includes_dir: &Path, offline: bool) -> Result<Prefetched, Box<dyn Error>> {
+    let cache_repo = cache_dir.join("repo");
+    if !cache_repo.join("config").exists() {
+        create_dir_all(&cache_repo)?;
+        ostree(&cache_repo, &["init", "--mode=archive-z2"])?;
+    }
+
+    let chroot_root = includes_dir.join(CHROOT_REPO.trim_start_matches('/')).parent().unwrap().to_path_buf();
+    remove_dir_all(&chroot_root).ok();
+    let keys_dir = includes_dir.join(CHROOT_KEYS.trim_start_matches('/'));
+    create_dir_all(&keys_dir)?;
+
+    let mut remotes: Vec<Remote> = Vec::new();
+    for remote in flatpaks.remotes.iter().flatten() {
+        remotes.push(add_remote(remote, &cache_repo, cache_dir, &keys_dir, offline)?);
+    }
+
+    // (remote, ref), requested refs first, their runtimes after
+    let mut refs: OrderSet<(String, String)> = OrderSet::new();
+    for flatpak_ref in flatpaks.refs.iter().flatten() {
+        if !remotes.iter().any(|r| r.name == flatpak_ref.remote) {
+            return Err(format!("Unknown flatpak remote `{}` for {}", flatpak_ref.remote, flatpak_ref.id).into());
+        }
+        refs.insert((flatpak_ref.remote.clone(), full_ref(flatpak_ref, architecture)?));
+    }
+    let requested: Vec<(String, String)> = refs.iter().cloned().collect();
+
+    let mut index = 0;
+    while index < refs.len() {
+        let (remote, flatpak_ref) = refs.get_index(index).unwrap().clone();
+        let remote_ref = format!("{}:{}", remote, flatpak_ref);
+        if offline {
+            if !is_cached(&cache_repo, &flatpak_ref)? {
+                return Err(format!("Flatpak {} is not cached, run `debr fetch` first", remote_ref).into());
+            }
+        } else {
+            println!("Fetching flatpak {} from {}", flatpak_ref, remote);
+            ostree(&cache_repo, &["pull", "--mirror", &remote, &flatpak_ref])
+                .map_err(|e| format!("Failed to fetch flatpak {}: {}", remote_ref, e))?;
+        }
+
+        if let Some(runtime) = runtime_of(&cache_repo, &flatpak_ref)? {
+            refs.insert((remote, format!("runtime/{}", runtime)));
+        }
+        index += 1;
+    }
+
+    // only the refs of this config are placed into the chroot
+    let chroot_repo = includes_dir.join(CHROOT_REPO.trim_start_matches('/'));
+    create_dir_all(&chroot_repo)?;
+    ostree(&chroot_repo, &["init", "--mode=archive-z2"])?;
+    let mut pull_local: Vec<String> = vec![s("pull-local"), cache_repo.to_string_lossy().to_string()];
+    pull_local.extend(refs.iter().map(|(_, r)| r.clone()));
+    ostree(&chroot_repo, &pull_local.iter().map(|a| a.as_str()).collect::<Vec<&str>>())?;
+    ostree(&chroot_repo, &["summary", "--update"])?;
+
+    Ok(Prefetched { remotes, refs: requested })
+}
+
+// `app/<id>/<arch>/<branch>`
+fn full_ref(flatpak_ref: &FlatpakRef, architecture: &str) -> Result<String, Box<dyn Error>> {
+    let kind = flatpak_ref.kind.clone().unwrap_or(s("app"));
+    if kind != "app" && kind != "runtime" {
+        return Err(format!("Invalid kind `{}` for flatpak {} (Allowed values: `app`, `runtime`)", kind, flatpak_ref.id).into());
+    }
+    if flatpak_ref.id.split('.').count() < 3 || flatpak_ref.id.contains('/') {
+        return Err(format!("Invalid flatpak id `{}`, expected e.g. `org.gimp.GIMP`", flatpak_ref.id).into());
+    }
+    let branch = flatpak_ref.branch.clone().unwrap_or(s("stable"));
+    Ok(format!("{}/{}/{}/{}", kind, flatpak_ref.id, flatpak_arch(architecture), branch))
+}
+
+// debian architecture to flatpak architecture
+fn flatpak_arch(architecture: &str) -> &str {
+    match architecture {
+        "amd64" => "x86_64",
+        "arm64" => "aarch64",
+        "armhf" => "arm",
+        other => other,
+    }
+}
+
+fn add_remote(remote: &FlatpakRemote, cache_repo: &Path, cache_dir: &Path, keys_dir: &Path, offline: bool) -> Result<Remote, Box<dyn Error>> {
+    // plain paths are local directory repos
+    let url = if remote.url.contains("://") {
+        remote.url.clone()
+    } else {
+        format!("file://{}", fs::canonicalize(&remote.url)?.display())
+    };
+
+    let mut args: Vec<String> = vec![s("remote"), s("add")];
+    let has_key = if let Some(key) = &remote.gpg_key {
+        let key_path = fetch_key(&remote.name, key, cache_dir, offline)?;
+        fs::copy(&key_path, keys_dir.join(format!("{}.gpg", remote.name)))?;
+        args.push(format!("--gpg-import={}", key_path.display()));
+        true
+    } else {
+        args.push(s("--no-gpg-verify"));
+        false
+    };
+    args.extend([remote.name.clone(), url.clone()]);
+
+    ostree(cache_repo, &["remote", "delete", "--if-exists", &remote.name])?;
+    ostree(cache_repo, &args.iter().map(|a| a.as_str()).collect::<Vec<&str>>())?;
+    Ok(Remote { name: remote.name.clone(), url, has_key })
+}
+
+// gpg key from an https URL or a local file
+fn fetch_key(name: &str, key: &str, cache_dir: &Path, offline: bool) -> Result<PathBuf, Box<dyn Error>> {
+    if !key.starts_with("https://") {
+        return Ok(fs::canonicalize(key)?);
+    }
+    let key_path = cache_dir.join(format!("{}.gpg", name));
+    if offline {
+        if !key_path.exists() {
+            return Err(format!("Key of flatpak remote {} is not cached, run `debr fetch` first", name).into());
+        }
+        return Ok(key_path);
+    }
+    let client = ClientBuilder::new().https_only(true).build()?;
+    let response = client.get(key).send()?;
+    if !response.status().is_success() {
+        return Err(format!("Failed to download flatpak remote key {}", key).into());
+    }
+    fs::write(&key_path, response.bytes()?)?;
+    Ok(key_path)
+}
+
+// `runtime=` of the ref's metadata, `None` for runtimes
+fn runtime_of(repo: &Path, flatpak_ref: &str) -> io::Result<Option<String>> {
+    if !flatpak_ref.starts_with("app/") {
+        return Ok(None);
+    }
+    let output = Command::new("ostree")
+        .arg(format!("--repo={}", repo.display()))
+        .args(["cat", flatpak_ref, "/metadata"])
+        .stderr(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!("Failed to read metadata of {}", flatpak_ref)));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("runtime="))
+        .map(|runtime| runtime.trim().to_string()))
+}
+
+// whether a previous pull mirrored the ref into the repo
+fn is_cached(repo: &Path, flatpak_ref: &str) -> io::Result<bool> {
+    Ok(Command::new("ostree")
+        .arg(format!("--repo={}", repo.display()))
+        .args(["rev-parse", flatpak_ref])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()?
+        .success())
+}
+
+fn ostree(repo: &Path, args: &[&str]) -> io::Result<()> {
+    let repo_arg = format!("--repo={}", repo.display());
+    let mut full_args = vec![repo_arg.as_str()];
+    full_args.extend_from_slice(args);
+    bash::cmd("ostree", full_args, None)
+}
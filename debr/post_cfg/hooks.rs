@@ -1,35 +1,25 @@
-use std::collections::HashSet;
 use std::fs::{create_dir_all,File, metadata, set_permissions};
 use std::os::unix::fs::PermissionsExt;
 use std::io::{self, Write};
 use std::path::Path;
 use ordermap::OrderSet;
 
+use super::{flatpak, tools};
+
 const DEF_SCRIPT: &str = "#!/bin/bash\n\
 echo \"I: running $0\"\n\n\
 set -e\n";
 
-const AS_USER: &str = r#"#!/bin/bash
-
-function as-user() {
-        _display_id=":$(find /tmp/.X11-unix/* | sed 's#/tmp/.X11-unix/X##' | head -n 1)"
-        _username="user"
-        _user_id=$(id -u "$_username")
-        _environment=("DISPLAY=$_display_id" "DBUS_SESSION_BUS_ADDRESS=unix:path=/run/user/$_user_id/bus")
-        sudo -Hu "$_username" "${_environment[@]}" "$@"
-    }
-"#;
-
-pub fn services(e_service: &HashSet<String>, d_service: &HashSet<String>) -> std::io::Result<String> {
+pub fn services(e_service: &OrderSet<String>, d_service: &OrderSet<String>) -> std::io::Result<String> {
     // https://github.com/nodiscc/debian-live-config/blob/55677bbd1d8fcfe522f090fb0d77bb1e16027f1d/config/hooks/normal/0350-update-default-services-status.hook.chroot
     let mut script = String::from(DEF_SCRIPT);
 
-    let gen_for_loop = |services: &HashSet<String>, action: &str| {
+    let gen_for_loop = |services: &OrderSet<String>, action: &str| {
         if services.is_empty() {
             return String::new();
         }
 
-        let services_str = escape_to_list(&services);
+        let services_str = escape_to_list(services);
         let loop_action = if action == "Disabling" { "disable" } else { "enable" };
         let systemctl_action = if action == "Disabling" { "stop" } else { "start" };
 
@@ -47,21 +37,26 @@ pub fn services(e_service: &HashSet<String>, d_service: &HashSet<String>) -> std
         script.push_str(&gen_for_loop(d_service, "Disabling"));
     }
     if !e_service.is_empty() {
-        if !d_service.is_empty() { script.push_str("\n"); }  // Add a newline between blocks if both are present
+        if !d_service.is_empty() { script.push('\n'); }  // Add a newline between blocks if both are present
         script.push_str(&gen_for_loop(e_service, "Enabling"));
     }
 
     Ok(script)
 }
 
-pub fn apt_install(packages: &HashSet<String>, apt:&str) -> std::io::Result<String> {
+pub fn apt_install(packages: &OrderSet<String>, apt:&str, preseed: Option<&str>) -> std::io::Result<String> {
     let mut script = String::from(DEF_SCRIPT);
+    if let Some(preseed) = preseed {
+        script.push_str("debconf-set-selections <<'DEBR_PRESEED'\n");
+        script.push_str(preseed);
+        script.push_str("DEBR_PRESEED\n\n");
+    }
     script.push_str("mv /tmp/apt-keyrings-cache-debr/*.gpg /etc/apt/keyrings/\n");
     script.push_str("rm -rf /tmp/apt-keyrings-cache-debr/\n");
     script.push_str(&format!("{} update\n\n", apt));
     
 
-    let packages_str = escape_to_list(&packages);
+    let packages_str = escape_to_list(packages);
 
     script.push_str(&format!(
         "echo \"Installing packages: {}\"\n",
@@ -75,43 +70,33 @@ pub fn apt_install(packages: &HashSet<String>, apt:&str) -> std::io::Result<Stri
         "DEBIAN_FRONTEND=noninteractive {} install -y{} {}\n",
         apt,no_recommends, packages_str
     ));
-    script.push_str("\n");
+    script.push('\n');
     script.push_str("echo \"Packages installed successfully.\"\n");
 
     Ok(script)
 }
 
-pub fn snap_install_from(packages: &OrderSet<String>, temp_path: &str) -> io::Result<String> {
+pub fn validate_seed(seed_path: &str) -> io::Result<String> {
     let mut script = String::from(DEF_SCRIPT);
-
-    let packages_str = packages.iter()
-        .map(|p| format!("\"{}\"", p.replace("\"", "\\\""))) // Escape quotes
-        .collect::<Vec<String>>()
-        .join(" ");
-    script.push_str("sleep 1\n");
-    script.push_str(&format!("snap_cache=\"{}\"\n", temp_path));
-    script.push_str(&format!("for package in {}; do\n", packages_str));
-    script.push_str("    set +e");
-    script.push_str("    echo \"Attempting to ack $package\"\n");
-    script.push_str("    snap ack \"$snap_cache/$package.assert\"\n");
-    script.push_str("    echo \"Attempting to install $package\"\n");
-    script.push_str("    snap install --classic \"$snap_cache/$package.snap\"\n");
-    script.push_str("    set -e");
-    script.push_str("    rm -f \"$snap_cache/$package.snap\"\n");
-    script.push_str("    rm -f \"$snap_cache/$package.assert\"\n");
-    script.push_str("done\n");
-    script.push_str("rm -rf \"$snap_cache\"\n");
-    script.push_str("rm /etc/systemd/system/snapd_installer.service\n");
-    script.push_str("systemctl disable \"snapd_installer.service\"");
-
+    script.push_str(&format!("snap debug validate-seed \"{}/seed.yaml\"\n", seed_path));
+    script.push_str("echo \"snapd seed is valid\"\n");
     Ok(script)
 }
 
-pub fn apt_purge(packages: &HashSet<String>) -> io::Result<String> {
+// `wanted` packages installed before purging are checked afterwards, removing one fails the hook in strict mode
+pub fn apt_purge(packages: &OrderSet<String>, wanted: &OrderSet<String>, strict: bool) -> io::Result<String> {
     let mut script = String::from(DEF_SCRIPT);
 
     let packages_str = escape_to_list(packages);
 
+    script.push_str(&format!("wanted=({})\n", escape_to_list(wanted)));
+    script.push_str("installed=()\n");
+    script.push_str("for package in \"${wanted[@]}\"; do\n");
+    script.push_str("    if dpkg-query -W -f='${db:Status-Abbrev}' \"$package\" 2>/dev/null | grep -q '^ii'; then\n");
+    script.push_str("        installed+=(\"$package\")\n");
+    script.push_str("    fi\n");
+    script.push_str("done\n\n");
+
     script.push_str(&format!("for package in {}; do\n", packages_str));
     script.push_str("    set +e\n");
     script.push_str("    apt purge --autoremove -y \"$package\"\n");
@@ -123,7 +108,21 @@ pub fn apt_purge(packages: &HashSet<String>) -> io::Result<String> {
     script.push_str("        echo \"Error: apt purge failed with exit code $exit_code\"\n");
     script.push_str("        exit $exit_code\n");
     script.push_str("    fi\n");
+    script.push_str("done\n\n");
+
+    let level = if strict { "E" } else { "W" };
+    script.push_str("removed=0\n");
+    script.push_str("for package in \"${installed[@]}\"; do\n");
+    script.push_str("    if ! dpkg-query -W -f='${db:Status-Abbrev}' \"$package\" 2>/dev/null | grep -q '^ii'; then\n");
+    script.push_str(&format!("        echo \"{}: $package was requested by include or extras, but removed by purging\"\n", level));
+    script.push_str("        removed=1\n");
+    script.push_str("    fi\n");
     script.push_str("done\n");
+    if strict {
+        script.push_str("if [ $removed -ne 0 ]; then\n");
+        script.push_str("    exit 1\n");
+        script.push_str("fi\n");
+    }
 
     script.push_str("echo \"Purge complete\"\n");
 
@@ -131,18 +130,89 @@ pub fn apt_purge(packages: &HashSet<String>) -> io::Result<String> {
 }
 
 
-pub fn gnome_set_dark() -> io::Result<String> {
-    let mut script = String::from(AS_USER);
-    script.push_str("set +e\n\n");
-    script.push_str("sleep 1\n");
-    script.push_str("as-user  gsettings set org.gnome.desktop.interface color-scheme 'prefer-dark'\n");
-    script.push_str("as-user  gsettings set org.gnome.desktop.interface gtk-theme 'Adwaita-dark'\n");
-    script.push_str("as-user  gsettings set org.gnome.desktop.wm.preferences button-layout ':minimize,maximize,close'\n");
-    script.push_str("as-user  gsettings set org.gnome.shell favorite-apps \"['code_code.desktop', 'google-chrome.desktop', 'org.gnome.Terminal.desktop']\"\n");
-    script.push_str("set -e\n");
-    script.push_str("rm /etc/systemd/system/apply_gnome_settings.service\n");
-    script.push_str("rm /lib/debr_util_scripts/apply_gnome_settings.sh\n");
-    script.push_str("systemctl disable \"apply_gnome_settings.service\"\n");
+pub fn dconf_update() -> io::Result<String> {
+    let mut script = String::from(DEF_SCRIPT);
+    script.push_str("dconf update\n");
+    script.push_str("echo \"dconf system databases compiled\"\n");
+    Ok(script)
+}
+
+pub fn xdg_autostart(apps: &[String]) -> io::Result<String> {
+    let mut script = String::from(DEF_SCRIPT);
+    let apps_str = escape_to_list(apps);
+
+    script.push_str("mkdir -p /etc/xdg/autostart\n");
+    script.push_str(&format!("for app in {}; do\n", apps_str));
+    script.push_str("    if [ -f \"/usr/share/applications/$app\" ]; then\n");
+    script.push_str("        cp \"/usr/share/applications/$app\" \"/etc/xdg/autostart/$app\"\n");
+    script.push_str("        echo \"Autostarting $app\"\n");
+    script.push_str("    else\n");
+    script.push_str("        echo \"W: $app not found in /usr/share/applications, not autostarting it\"\n");
+    script.push_str("    fi\n");
+    script.push_str("done\n");
+    Ok(script)
+}
+
+pub fn check_binaries(binaries: &OrderSet<String>) -> io::Result<String> {
+    let mut script = String::from(DEF_SCRIPT);
+    let binaries_str = escape_to_list(binaries);
+
+    script.push_str("missing=0\n");
+    script.push_str(&format!("for binary in {}; do\n", binaries_str));
+    script.push_str("    case \"$binary\" in\n");
+    script.push_str("        /snap/bin/*) echo \"W: $binary is provided by a snap, skipping check\"; continue ;;\n");
+    script.push_str("    esac\n");
+    script.push_str("    if ! command -v \"$binary\" > /dev/null; then\n");
+    script.push_str("        echo \"E: $binary referenced by a desktop entry does not exist in the chroot\"\n");
+    script.push_str("        missing=1\n");
+    script.push_str("    fi\n");
+    script.push_str("done\n");
+    script.push_str("exit $missing\n");
+    Ok(script)
+}
+
+pub fn flatpak_install(prefetched: &flatpak::Prefetched) -> io::Result<String> {
+    let (remotes, refs) = (&prefetched.remotes, &prefetched.refs);
+    let mut script = String::from(DEF_SCRIPT);
+    script.push_str(&format!("repo=\"{}\"\n", flatpak::CHROOT_REPO));
+    script.push_str(&format!("keys=\"{}\"\n\n", flatpak::CHROOT_KEYS));
+
+    // install from the prefetched repo under the remote's name, then point the remote to its real url
+    for remote in remotes {
+        script.push_str(&format!("flatpak remote-add --system --if-not-exists --no-gpg-verify \"{}\" \"file://$repo\"\n", remote.name));
+    }
+    for (remote, flatpak_ref) in refs {
+        script.push_str(&format!("echo \"Installing flatpak {}\"\n", flatpak_ref));
+        script.push_str(&format!("flatpak install --system --noninteractive --no-related \"{}\" \"{}\"\n", remote, flatpak_ref));
+    }
+    for remote in remotes {
+        script.push_str(&format!("flatpak remote-modify --system --url=\"{}\" \"{}\"\n", remote.url, remote.name));
+        if remote.has_key {
+            script.push_str(&format!("flatpak remote-modify --system --gpg-verify --gpg-import=\"$keys/{}.gpg\" \"{}\"\n", remote.name, remote.name));
+        }
+    }
+    script.push_str("\nrm -rf \"$(dirname \"$repo\")\"\n");
+    script.push_str("echo \"Flatpaks installed successfully.\"\n");
+    Ok(script)
+}
+
+pub fn tool_install(installs: &[tools::Install]) -> io::Result<String> {
+    let mut script = String::from(DEF_SCRIPT);
+    script.push_str("failed=()\n");
+    for install in installs {
+        let package = install.package.replace("\"", "\\\"");
+        script.push_str(&format!("echo \"Installing {} package {}\"\n", install.ecosystem, package));
+        script.push_str(&format!("if ! {}; then\n", install.command));
+        script.push_str(&format!("    echo \"E: failed to install {} package {}\"\n", install.ecosystem, package));
+        script.push_str(&format!("    failed+=(\"{} {}\")\n", install.ecosystem, package));
+        script.push_str("fi\n");
+    }
+    script.push_str("rm -rf /tmp/debr-npm /tmp/debr-cargo\n\n");
+    script.push_str("if [ ${#failed[@]} -ne 0 ]; then\n");
+    script.push_str("    printf 'E: failed tool package: %s\\n' \"${failed[@]}\"\n");
+    script.push_str("    exit 1\n");
+    script.push_str("fi\n");
+    script.push_str("echo \"Tool packages installed successfully.\"\n");
     Ok(script)
 }
 
@@ -165,13 +235,13 @@ pub fn add_hook(name: &str, content: &String, live_dir: &Path, at_boot: bool) ->
 }
 
 
-fn escape_to_list(set: &HashSet<String>) -> String {
+fn escape_to_list<'a>(set: impl IntoIterator<Item = &'a String>) -> String {
     let escaped = set
-        .iter()
+        .into_iter()
         .map(|p| format!("\"{}\"", p.replace("\"", "\\\""))) // Escape quotes
         .collect::<Vec<String>>()
         .join(" ");
-    return escaped;
+    escaped
 }
 
 pub fn chmod_x<P: AsRef<Path>>(path: P) -> std::io::Result<()> {
@@ -184,23 +254,3 @@ pub fn chmod_x<P: AsRef<Path>>(path: P) -> std::io::Result<()> {
     set_permissions(path, permissions)?;
     Ok(())
 }
-
-pub fn logger_wrap(script: &str) -> String {
-    let mut wrapped_script = String::new();
-
-    wrapped_script.push_str("#!/bin/bash\n");
-    wrapped_script.push_str("LOG_FILE=/tmp/debr_boot_script.log\n");
-    wrapped_script.push_str("set -e \n");
-    wrapped_script.push_str("exec &>> $LOG_FILE\n");
-    wrapped_script.push_str("echo \"I: running $0\"\n\n");
-
-    // Append the original script
-    wrapped_script.push_str(script);
-
-    wrapped_script.push_str("\n");
-
-    // Final message indicating script execution completion
-    wrapped_script.push_str("echo \"Script completed successfully.\" >> $LOG_FILE\n");
-
-    wrapped_script
-}
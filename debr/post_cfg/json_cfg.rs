@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::fs::File;
 use std::io::BufReader;
@@ -14,62 +15,304 @@ pub struct Config {
     pub archive_areas: Option<String>,
     pub recommends: Option<bool>,
     pub apt: Option<String>,
-    pub include: Option<Vec<String>>,
+    pub include: Option<Vec<IncludeSpec>>,
     pub purge: Option<Vec<String>>,
-    pub snaps: Option<Vec<String>>,
+    pub validate_packages: Option<bool>,
+    pub package_index_dir: Option<String>,
+    pub snaps: Option<Vec<SnapSpec>>,
+    pub snap_store: Option<String>,
+    pub flatpaks: Option<Flatpaks>,
+    pub downloads: Option<Vec<Download>>,
+    pub tool_packages: Option<ToolPackages>,
 
     pub extras: Option<Vec<Extra>>,
     pub keyringer: Option<bool>,
+    pub desktop: Option<String>,
     pub dark_mode: Option<bool>,
+    pub theme: Option<String>,
+    pub favorite_apps: Option<Vec<String>>,
+    pub wallpaper: Option<String>,
+    pub autostart: Option<Vec<String>>,
+    pub autostart_entries: Option<Vec<DesktopEntry>>,
+    pub desktop_entries: Option<Vec<DesktopEntry>>,
+    pub mime_defaults: Option<BTreeMap<String, String>>,
+    pub browser_policies: Option<BrowserPolicies>,
+    pub debconf: Option<Vec<DebconfSelection>>,
+    pub gsettings: Option<Vec<GSetting>>,
     pub de_boot_opts: Option<String>,
+    pub debootstrap: Option<Debootstrap>,
     pub requires: Option<Vec<String>>,
-    pub e_service: Option<HashSet<String>>,
-    pub d_service: Option<HashSet<String>>,
+    pub e_service: Option<Vec<String>>,
+    pub d_service: Option<Vec<String>>,
+
+    pub source_date_epoch: Option<u64>,
+    pub snapshot: Option<SnapshotSpec>,
+    pub mirrors: Option<Mirrors>,
 
     pub lang: Option<String>,
     
 }
 
+// a package name or a package with options
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum IncludeSpec {
+    Name(String),
+    Package(Package),
+}
+
+impl IncludeSpec {
+    pub fn into_package(self) -> Package {
+        match self {
+            IncludeSpec::Name(name) => Package { name, ..Default::default() },
+            IncludeSpec::Package(package) => package,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Package {
+    pub name: String,
+    pub stage: Option<String>,
+    pub arch: Option<Vec<String>>,
+    pub list: Option<String>,
+}
+
+// a snapshot timestamp or URL, or a snapshot with options
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum SnapshotSpec {
+    At(String),
+    Snapshot(Snapshot),
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Snapshot {
+    pub at: String,
+    pub restore: Option<bool>,
+}
+
+// live-build mirrors, `binary` ones end up in the final image
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Mirrors {
+    pub bootstrap: Option<String>,
+    pub chroot: Option<String>,
+    pub security: Option<String>,
+    pub binary: Option<String>,
+    pub binary_security: Option<String>,
+    pub apt_proxy: Option<String>,
+    // port `debr --offline build` serves the apt mirror on
+    pub offline_port: Option<u16>,
+}
+
+// a snap name or a snap with options
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum SnapSpec {
+    Name(String),
+    Snap(Snap),
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Snap {
+    pub name: String,
+    pub channel: Option<String>,
+    pub revision: Option<u64>,
+    pub confinement: Option<String>,
+}
+
+impl SnapSpec {
+    pub fn into_snap(self) -> Snap {
+        match self {
+            SnapSpec::Name(name) => Snap { name, ..Default::default() },
+            SnapSpec::Snap(snap) => snap,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Flatpaks {
+    pub remotes: Option<Vec<FlatpakRemote>>,
+    pub refs: Option<Vec<FlatpakRef>>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FlatpakRemote {
+    pub name: String,
+    pub url: String,
+    pub gpg_key: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FlatpakRef {
+    pub id: String,
+    pub remote: String,
+    pub branch: Option<String>,
+    pub kind: Option<String>,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)] // Added Clone here
 #[serde(rename_all = "camelCase")]
 pub struct Extra {
     pub name: String,
     pub key: String,
     pub src: String,
-    pub add: HashSet<String>,
+    pub add: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct GSetting {
+    pub schema: String,
+    pub key: String,
+    pub value: Value,
+    pub path: Option<String>,
+    pub raw: Option<bool>,
+    pub lock: Option<bool>,
 }
 
 pub fn merge(this_config:&Config, other_config: &Config) -> Result<Config, Box<dyn Error>> {
     let this_json = serde_json::to_value(this_config)?;
-    let other_json = serde_json::to_value(&other_config)?;
-    let mut this_map = this_json.as_object().unwrap().clone();
+    let other_json = serde_json::to_value(other_config)?;
+    let this_map = this_json.as_object().unwrap();
     let other_map = other_json.as_object().unwrap();
 
+    let new_value = serde_json::Value::Object(merge_map(this_map, other_map, "")?);
+    println!("{}\n", new_value);
+    let new_config: Config = serde_json::from_value(new_value)?;
+
+    Ok(new_config)
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DesktopEntry {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub entry_type: Option<String>,
+    pub name: String,
+    pub exec: Option<String>,
+    pub url: Option<String>,
+    pub icon: Option<String>,
+    pub comment: Option<String>,
+    pub categories: Option<Vec<String>>,
+    pub mime_types: Option<Vec<String>>,
+    pub terminal: Option<bool>,
+    pub no_display: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BrowserPolicies {
+    pub homepage: Option<String>,
+    pub extensions: Option<BrowserExtensions>,
+    pub bookmarks: Option<Vec<Bookmark>>,
+    pub disabled: Option<Vec<String>>,
+    pub chrome: Option<BTreeMap<String, Value>>,
+    pub firefox: Option<BTreeMap<String, Value>>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BrowserExtensions {
+    pub chrome: Option<Vec<String>>,
+    pub firefox: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Bookmark {
+    pub name: String,
+    pub url: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DebconfSelection {
+    pub package: String,
+    pub question: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub value: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Debootstrap {
+    pub variant: Option<String>,
+    pub include: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+    pub components: Option<Vec<String>>,
+    pub keyring: Option<String>,
+}
+
+// a file fetched at config time and verified by its sha256
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Download {
+    pub name: String,
+    pub url: String,
+    pub sha256: String,
+    pub target: String,
+    pub extract: Option<bool>,
+    pub strip_components: Option<u32>,
+    pub file_name: Option<String>,
+    pub mode: Option<String>,
+    pub bin: Option<String>,
+    pub symlink: Option<bool>,
+    pub desktop_entry: Option<DesktopEntry>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolPackages {
+    pub pipx: Option<ToolSet>,
+    pub npm: Option<ToolSet>,
+    pub cargo: Option<ToolSet>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolSet {
+    pub index_url: Option<String>,
+    pub packages: Option<Vec<String>>,
+}
+
+// arrays are concatenated, objects merged by key, differing values are a conflict
+fn merge_map(this_map: &Map<String, Value>, other_map: &Map<String, Value>, prefix: &str) -> Result<Map<String, Value>, Box<dyn Error>> {
+    let mut new_map = this_map.clone();
+
     for (key, other_value) in other_map {
-            if let Some(this_value) = this_json.get(key){
+            let field = format!("{}{}", prefix, key);
+            if let Some(this_value) = this_map.get(key){
                 if other_value.is_null(){}
                 else if this_value.is_null(){
-                    this_map.insert(key.clone(), other_value.clone());
+                    new_map.insert(key.clone(), other_value.clone());
                 }
                 else if this_value.is_array() && other_value.is_array(){
                     let mut this_array= this_value.as_array().unwrap().clone();
                     this_array.extend_from_slice(other_value.as_array().unwrap());
-                    let new_array = serde_json::to_value(this_array)?;
-                    this_map.insert(key.clone(), new_array);
+                    new_map.insert(key.clone(), Value::Array(this_array));
+                }
+                else if this_value.is_object() && other_value.is_object(){
+                    let merged = merge_map(this_value.as_object().unwrap(), other_value.as_object().unwrap(), &format!("{}.", field))?;
+                    new_map.insert(key.clone(), Value::Object(merged));
                 }else if this_value != other_value {
-                    return Err(format!("Conflict in `{}` field\nThisValue:\n{}\nOtherValue:\n{}", key, this_value, other_value).into());
+                    return Err(format!("Conflict in `{}` field\nThisValue:\n{}\nOtherValue:\n{}", field, this_value, other_value).into());
                 }
             }else{
-                this_map.insert(key.clone(), other_value.clone());
+                new_map.insert(key.clone(), other_value.clone());
             }
         }
-    
-    
-    let new_value = serde_json::Value::Object(this_map);
-    println!("{}\n", new_value.to_string());
-    let new_config: Config = serde_json::from_value(new_value)?;
 
-    Ok(new_config)
+    Ok(new_map)
 }
 
 pub fn add(this_config: &Config, path: &Path) -> Result<Config, Box<dyn Error>> {
@@ -105,10 +348,20 @@ pub fn read_config(path: &Path) -> Result<Config, Box<dyn Error>> {
         println!("[Config       ] {}", final_path.display());
     }
 
-    let file = File::open(final_path)?;
+    let file = File::open(&final_path)?;
     let reader = BufReader::new(file);
     let mut config: Config = serde_json::from_reader(reader)?;
 
+    // packages are listed per config file, unless set otherwise
+    let list = final_path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+    if let Some(include) = config.include.take() {
+        config.include = Some(include.into_iter().map(|spec| {
+            let mut package = spec.into_package();
+            package.list.get_or_insert_with(|| list.clone());
+            IncludeSpec::Package(package)
+        }).collect());
+    }
+
     if let Some(requires) = config.requires.clone() {
         for required_path in requires.iter() {
             config = add(&config, Path::new(&required_path))?;
@@ -0,0 +1,425 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::io;
+use std::path::Path;
+use ordermap::OrderMap;
+
+use super::cfg_parser::{quote, ShellConfig};
+use super::s;
+
+pub const FILES: [&str; 5] = ["bootstrap", "common", "chroot", "binary", "source"];
+
+// a typed value of a live-build variable
+pub trait LbValue: Sized {
+    // `None` if the file doesn't assign the variable, `Err` with the raw value if it's invalid for the type
+    fn get(config: &ShellConfig, variable: &str) -> Option<Result<Self, String>>;
+    fn set(&self, config: &mut ShellConfig, variable: &str);
+    fn render(&self) -> String;
+}
+
+impl LbValue for String {
+    fn get(config: &ShellConfig, variable: &str) -> Option<Result<Self, String>> {
+        config.get(variable).map(|value| Ok(value.to_string()))
+    }
+    fn set(&self, config: &mut ShellConfig, variable: &str) {
+        config.set(variable, self);
+    }
+    fn render(&self) -> String {
+        self.clone()
+    }
+}
+
+impl LbValue for bool {
+    fn get(config: &ShellConfig, variable: &str) -> Option<Result<Self, String>> {
+        let raw = config.get(variable)?;
+        Some(config.get_bool(variable).ok_or_else(|| raw.to_string()))
+    }
+    fn set(&self, config: &mut ShellConfig, variable: &str) {
+        config.set_bool(variable, *self);
+    }
+    fn render(&self) -> String {
+        self.to_string()
+    }
+}
+
+// whitespace separated, e.g. LB_BOOTAPPEND_LIVE
+impl LbValue for Vec<String> {
+    fn get(config: &ShellConfig, variable: &str) -> Option<Result<Self, String>> {
+        config.get_list(variable).map(Ok)
+    }
+    fn set(&self, config: &mut ShellConfig, variable: &str) {
+        config.set_list(variable, self);
+    }
+    fn render(&self) -> String {
+        self.join(" ")
+    }
+}
+
+fn list(value: &str) -> Vec<String> {
+    value.split_whitespace().map(s).collect()
+}
+
+// a variable which differs between two configs
+pub struct Change {
+    pub variable: String,
+    pub this: Option<String>,
+    pub other: Option<String>,
+}
+
+// live-build's default of LB_DISTRIBUTION, the other distributions default to LB_DISTRIBUTION
+const DISTRIBUTION: &str = "bookworm";
+
+// one struct per file of config/, with the variables, their types and live-build's defaults,
+// which may depend on the distribution
+macro_rules! lb_config {
+    (|$distribution:ident| $($section:ident: $Section:ident = $file:literal {
+        $($field:ident: $ty:ty = $var:literal, $default:expr;)*
+    })*) => {
+        $(
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct $Section {
+            $(pub $field: $ty,)*
+        }
+
+        impl $Section {
+            // not every section has defaults depending on the distribution
+            #[allow(unused_variables)]
+            fn defaults($distribution: &str) -> Self {
+                $Section { $($field: $default,)* }
+            }
+
+            fn load(files: &OrderMap<&'static str, ShellConfig>, distribution: &str) -> Result<Self, String> {
+                let defaults = Self::defaults(distribution);
+                Ok($Section {
+                    // the first file assigning the variable
+                    $($field: match files.values().find_map(|config| <$ty as LbValue>::get(config, $var)) {
+                        Some(Ok(value)) => value,
+                        Some(Err(raw)) if raw.is_empty() => defaults.$field,
+                        Some(Err(raw)) => return Err(format!("Invalid value `{}` for {} in config/{}", raw, $var, $file)),
+                        None => defaults.$field,
+                    },)*
+                })
+            }
+
+            // (variable, value, is default)
+            fn variables(&self, distribution: &str) -> Vec<(&'static str, String, bool)> {
+                let defaults = Self::defaults(distribution);
+                vec![$(($var, self.$field.render(), self.$field == defaults.$field)),*]
+            }
+
+            // `false` if the variable isn't one of this section
+            fn set(&self, variable: &str, config: &mut ShellConfig) -> bool {
+                match variable {
+                    $($var => self.$field.set(config, $var),)*
+                    _ => return false,
+                }
+                true
+            }
+        }
+        )*
+
+        // the variables of config/{bootstrap,common,chroot,binary,source}
+        pub struct LbConfig {
+            $(pub $section: $Section,)*
+            files: OrderMap<&'static str, ShellConfig>,
+        }
+
+        impl LbConfig {
+            // missing variables and files fall back to live-build's defaults
+            pub fn load(live_dir: &Path) -> Result<Self, Box<dyn Error>> {
+                let mut files = OrderMap::new();
+                for file in FILES {
+                    let path = live_dir.join("config").join(file);
+                    if path.exists() {
+                        files.insert(file, ShellConfig::load(&path)?);
+                    }
+                }
+                Self::from_files(files)
+            }
+
+            // from values such as the ones of `values`, e.g. to diff against, `save` doesn't apply
+            pub fn from_values(values: &BTreeMap<String, String>) -> Result<Self, Box<dyn Error>> {
+                // one file holds them all, the variables are looked up in every file
+                let mut config = ShellConfig::new();
+                for (variable, value) in values {
+                    config.set(variable, value);
+                }
+                Self::from_files(OrderMap::from([("common", config)]))
+            }
+
+            fn from_files(files: OrderMap<&'static str, ShellConfig>) -> Result<Self, Box<dyn Error>> {
+                let distribution = files.values()
+                    .find_map(|config| config.get("LB_DISTRIBUTION"))
+                    .filter(|distribution| !distribution.is_empty())
+                    .unwrap_or(DISTRIBUTION)
+                    .to_string();
+                Ok(LbConfig {
+                    $($section: $Section::load(&files, &distribution)?,)*
+                    files,
+                })
+            }
+
+            // (file, variable, value, is default) of every typed variable
+            pub fn variables(&self) -> Vec<(&'static str, &'static str, String, bool)> {
+                let distribution = &self.bootstrap.distribution;
+                let mut variables = Vec::new();
+                $(variables.extend(self.$section.variables(distribution).into_iter().map(|(var, value, default)| ($file, var, value, default)));)*
+                variables
+            }
+
+            // assigns the typed value of the variable in `file`
+            fn assign(&mut self, file: &str, variable: &str) {
+                let config = self.files.get_mut(file).unwrap();
+                $(if self.$section.set(variable, config) {
+                    return;
+                })*
+            }
+        }
+    };
+}
+
+lb_config! {
+    |distribution|
+    bootstrap: Bootstrap = "bootstrap" {
+        architecture: String = "LB_ARCHITECTURE", s("amd64");
+        distribution: String = "LB_DISTRIBUTION", s(DISTRIBUTION);
+        parent_distribution: String = "LB_PARENT_DISTRIBUTION", s(distribution);
+        distribution_chroot: String = "LB_DISTRIBUTION_CHROOT", s(distribution);
+        distribution_binary: String = "LB_DISTRIBUTION_BINARY", s(distribution);
+        archive_areas: Vec<String> = "LB_ARCHIVE_AREAS", list("main");
+        parent_archive_areas: Vec<String> = "LB_PARENT_ARCHIVE_AREAS", list("main");
+        mirror_bootstrap: String = "LB_MIRROR_BOOTSTRAP", s("http://deb.debian.org/debian/");
+        parent_mirror_bootstrap: String = "LB_PARENT_MIRROR_BOOTSTRAP", s("http://deb.debian.org/debian/");
+        mirror_chroot: String = "LB_MIRROR_CHROOT", s("http://deb.debian.org/debian/");
+        parent_mirror_chroot: String = "LB_PARENT_MIRROR_CHROOT", s("http://deb.debian.org/debian/");
+        mirror_chroot_security: String = "LB_MIRROR_CHROOT_SECURITY", s("http://security.debian.org/");
+        parent_mirror_chroot_security: String = "LB_PARENT_MIRROR_CHROOT_SECURITY", s("http://security.debian.org/");
+        mirror_binary: String = "LB_MIRROR_BINARY", s("http://deb.debian.org/debian/");
+        parent_mirror_binary: String = "LB_PARENT_MIRROR_BINARY", s("http://deb.debian.org/debian/");
+        mirror_binary_security: String = "LB_MIRROR_BINARY_SECURITY", s("http://security.debian.org/");
+        parent_mirror_binary_security: String = "LB_PARENT_MIRROR_BINARY_SECURITY", s("http://security.debian.org/");
+        mirror_debian_installer: String = "LB_MIRROR_DEBIAN_INSTALLER", s("http://deb.debian.org/debian/");
+        parent_mirror_debian_installer: String = "LB_PARENT_MIRROR_DEBIAN_INSTALLER", s("http://deb.debian.org/debian/");
+    }
+    common: Common = "common" {
+        apt: String = "LB_APT", s("apt");
+        apt_ftp_proxy: String = "LB_APT_FTP_PROXY", s("");
+        apt_http_proxy: String = "LB_APT_HTTP_PROXY", s("");
+        apt_pipeline: String = "LB_APT_PIPELINE", s("");
+        apt_recommends: bool = "LB_APT_RECOMMENDS", true;
+        apt_secure: bool = "LB_APT_SECURE", true;
+        apt_source_archives: bool = "LB_APT_SOURCE_ARCHIVES", true;
+        cache: bool = "LB_CACHE", true;
+        cache_indices: bool = "LB_CACHE_INDICES", false;
+        cache_packages: bool = "LB_CACHE_PACKAGES", true;
+        cache_stages: Vec<String> = "LB_CACHE_STAGES", list("bootstrap");
+        debconf_frontend: String = "LB_DEBCONF_FRONTEND", s("noninteractive");
+        debconf_priority: String = "LB_DEBCONF_PRIORITY", s("critical");
+        initramfs: String = "LB_INITRAMFS", s("live-boot");
+        initramfs_compression: String = "LB_INITRAMFS_COMPRESSION", s("gzip");
+        initsystem: String = "LB_INITSYSTEM", s("systemd");
+        fdisk: String = "LB_FDISK", s("fdisk");
+        losetup: String = "LB_LOSETUP", s("losetup");
+        mode: String = "LB_MODE", s("debian");
+        system: String = "LB_SYSTEM", s("live");
+        tasksel: String = "LB_TASKSEL", s("apt");
+        build_with_chroot: bool = "LB_BUILD_WITH_CHROOT", true;
+        apt_options: String = "APT_OPTIONS", s("--yes -o Acquire::Retries=5");
+        aptitude_options: String = "APTITUDE_OPTIONS", s("--assume-yes -o Acquire::Retries=5");
+        debootstrap_options: String = "DEBOOTSTRAP_OPTIONS", s("");
+        debootstrap_script: String = "DEBOOTSTRAP_SCRIPT", s("");
+        gzip_options: String = "GZIP_OPTIONS", s("-6 --rsyncable");
+        isohybrid_options: String = "ISOHYBRID_OPTIONS", s("");
+        source_date_epoch: String = "SOURCE_DATE_EPOCH", s("");
+        bootstrap_qemu_architecture: String = "LB_BOOTSTRAP_QEMU_ARCHITECTURE", s("");
+        bootstrap_qemu_exclude: Vec<String> = "LB_BOOTSTRAP_QEMU_EXCLUDE", list("");
+        bootstrap_qemu_static: String = "LB_BOOTSTRAP_QEMU_STATIC", s("");
+    }
+    chroot: Chroot = "chroot" {
+        chroot_filesystem: String = "LB_CHROOT_FILESYSTEM", s("squashfs");
+        union_filesystem: String = "LB_UNION_FILESYSTEM", s("overlay");
+        interactive: bool = "LB_INTERACTIVE", false;
+        keyring_packages: Vec<String> = "LB_KEYRING_PACKAGES", list("debian-archive-keyring");
+        linux_flavours: Vec<String> = "LB_LINUX_FLAVOURS_WITH_ARCH", list("amd64");
+        linux_packages: Vec<String> = "LB_LINUX_PACKAGES", list("linux-image");
+        security: bool = "LB_SECURITY", true;
+        updates: bool = "LB_UPDATES", true;
+        backports: bool = "LB_BACKPORTS", false;
+        proposed_updates: bool = "LB_PROPOSED_UPDATES", false;
+    }
+    binary: Binary = "binary" {
+        image_name: String = "LB_IMAGE_NAME", s("live-image");
+        image_type: String = "LB_IMAGE_TYPE", s("iso-hybrid");
+        binary_filesystem: String = "LB_BINARY_FILESYSTEM", s("fat32");
+        apt_indices: bool = "LB_APT_INDICES", true;
+        bootappend_install: Vec<String> = "LB_BOOTAPPEND_INSTALL", list("");
+        bootappend_live: Vec<String> = "LB_BOOTAPPEND_LIVE", list("boot=live components quiet splash");
+        bootappend_live_failsafe: Vec<String> = "LB_BOOTAPPEND_LIVE_FAILSAFE", list("boot=live components memtest noapic noapm nodma nomce nolapic nosmp nosplash vga=788");
+        bootloader_bios: String = "LB_BOOTLOADER_BIOS", s("syslinux");
+        bootloader_efi: String = "LB_BOOTLOADER_EFI", s("grub-efi");
+        checksums: Vec<String> = "LB_CHECKSUMS", list("sha256");
+        compression: String = "LB_COMPRESSION", s("none");
+        zsync: bool = "LB_ZSYNC", false;
+        debian_installer: String = "LB_DEBIAN_INSTALLER", s("none");
+        debian_installer_distribution: String = "LB_DEBIAN_INSTALLER_DISTRIBUTION", s(distribution);
+        debian_installer_gui: bool = "LB_DEBIAN_INSTALLER_GUI", true;
+        firmware_binary: bool = "LB_FIRMWARE_BINARY", true;
+        firmware_chroot: bool = "LB_FIRMWARE_CHROOT", true;
+        hdd_label: String = "LB_HDD_LABEL", s("DEBIAN_LIVE");
+        hdd_size: String = "LB_HDD_SIZE", s("auto");
+        iso_application: String = "LB_ISO_APPLICATION", s("Debian Live");
+        iso_preparer: String = "LB_ISO_PREPARER", s("live-build @LB_VERSION@; https://salsa.debian.org/live-team/live-build");
+        iso_publisher: String = "LB_ISO_PUBLISHER", s("Debian Live project; https://wiki.debian.org/DebianLive; debian-live@lists.debian.org");
+        iso_volume: String = "LB_ISO_VOLUME", format!("Debian {} $(date --utc -d@${{SOURCE_DATE_EPOCH}} +%Y%m%d-%H:%M)", distribution);
+        memtest: String = "LB_MEMTEST", s("none");
+        onie: bool = "LB_ONIE", false;
+        swap_file_path: String = "LB_SWAP_FILE_PATH", s("");
+        swap_file_size: String = "LB_SWAP_FILE_SIZE", s("512");
+        uefi_secure_boot: String = "LB_UEFI_SECURE_BOOT", s("auto");
+        win32_loader: bool = "LB_WIN32_LOADER", false;
+    }
+    source: Source = "source" {
+        source: bool = "LB_SOURCE", false;
+        source_images: Vec<String> = "LB_SOURCE_IMAGES", list("tar");
+    }
+}
+
+impl LbConfig {
+    // variables of the files which aren't typed, (file, variable, value)
+    pub fn untyped(&self) -> Vec<(&'static str, &str, &str)> {
+        let typed: Vec<&str> = self.variables().into_iter().map(|(_, var, _, _)| var).collect();
+        let mut untyped = Vec::new();
+        for (file, config) in &self.files {
+            for key in config.keys() {
+                if !typed.contains(&key) {
+                    untyped.push((*file, key, config.get(key).unwrap_or_default()));
+                }
+            }
+        }
+        untyped
+    }
+
+    // values of the typed and untyped variables
+    pub fn values(&self) -> BTreeMap<String, String> {
+        let mut values: BTreeMap<String, String> = self.variables().into_iter().map(|(_, var, value, _)| (s(var), value)).collect();
+        for (_, var, value) in self.untyped() {
+            values.insert(s(var), s(value));
+        }
+        values
+    }
+
+    // variables with another value in `other`, or which only one of both has
+    pub fn diff(&self, other: &LbConfig) -> Vec<Change> {
+        let (this, other) = (self.values(), other.values());
+        let mut changes = Vec::new();
+        for (variable, value) in &this {
+            if other.get(variable) != Some(value) {
+                changes.push(Change { variable: variable.clone(), this: Some(value.clone()), other: other.get(variable).cloned() });
+            }
+        }
+        for (variable, value) in &other {
+            if !this.contains_key(variable) {
+                changes.push(Change { variable: variable.clone(), this: None, other: Some(value.clone()) });
+            }
+        }
+        changes
+    }
+
+    // writes changed variables back, keeping the rest of the files as they are
+    pub fn save(&mut self, live_dir: &Path) -> io::Result<()> {
+        for (file, var, value, default) in self.variables() {
+            let file = self.files.keys().copied()
+                .find(|f| self.files[f].get(var).is_some())
+                .unwrap_or(file);
+            let current = self.files.get(file).and_then(|config| config.get(var));
+            if current == Some(value.as_str()) || (current.is_none() && default) {
+                continue;
+            }
+            if !self.files.contains_key(file) {
+                let path = live_dir.join("config").join(file);
+                if !path.exists() {
+                    std::fs::write(&path, "")?;
+                }
+                self.files.insert(file, ShellConfig::load(&path)?);
+            }
+            self.assign(file, var);
+        }
+        for config in self.files.values() {
+            config.save()?;
+        }
+        Ok(())
+    }
+
+    // values with `$(...)` or backticks are shown as written, live-build's defaults included
+    fn quote(&self, variable: &str, value: &str) -> String {
+        let dynamic = match self.files.values().find(|config| config.get(variable).is_some()) {
+            Some(config) => config.is_dynamic(variable),
+            None => value.contains("$(") || value.contains('`'),
+        };
+        if dynamic {
+            return format!("\"{}\"", value);
+        }
+        quote(value)
+    }
+
+    // effective settings, defaults marked
+    pub fn show(&self) -> String {
+        let mut output = String::new();
+        let mut current_file = "";
+        for (file, var, value, default) in self.variables() {
+            if file != current_file {
+                let _ = writeln!(output, "{}# config/{}", if current_file.is_empty() { "" } else { "\n" }, file);
+                current_file = file;
+            }
+            let _ = writeln!(output, "{}={}{}", var, self.quote(var, &value), if default { "  # default" } else { "" });
+        }
+        let untyped = self.untyped();
+        if !untyped.is_empty() {
+            let _ = writeln!(output, "\n# other");
+            for (file, var, value) in untyped {
+                let _ = writeln!(output, "{}={}  # config/{}", var, self.quote(var, value), file);
+            }
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(values: &[(&str, &str)]) -> LbConfig {
+        LbConfig::from_values(&values.iter().map(|(variable, value)| (s(variable), s(value))).collect()).unwrap()
+    }
+
+    #[test]
+    fn diff() {
+        let this = config(&[("LB_ARCHITECTURE", "arm64"), ("LB_BOOTAPPEND_LIVE", "boot=live quiet"), ("MY_VARIABLE", "1")]);
+        let other = config(&[("LB_DISTRIBUTION", "trixie"), ("LB_BOOTAPPEND_LIVE", "boot=live  quiet"), ("OTHER_VARIABLE", "2")]);
+        let changes = this.diff(&other);
+        let changes: Vec<(&str, Option<&str>, Option<&str>)> = changes.iter()
+            .map(|c| (c.variable.as_str(), c.this.as_deref(), c.other.as_deref()))
+            .collect();
+        assert_eq!(changes, [
+            ("LB_ARCHITECTURE", Some("arm64"), Some("amd64")),
+            // the defaults derived from the distribution change with it
+            ("LB_DEBIAN_INSTALLER_DISTRIBUTION", Some("bookworm"), Some("trixie")),
+            ("LB_DISTRIBUTION", Some("bookworm"), Some("trixie")),
+            ("LB_DISTRIBUTION_BINARY", Some("bookworm"), Some("trixie")),
+            ("LB_DISTRIBUTION_CHROOT", Some("bookworm"), Some("trixie")),
+            ("LB_ISO_VOLUME", Some("Debian bookworm $(date --utc -d@${SOURCE_DATE_EPOCH} +%Y%m%d-%H:%M)"), Some("Debian trixie $(date --utc -d@${SOURCE_DATE_EPOCH} +%Y%m%d-%H:%M)")),
+            ("LB_PARENT_DISTRIBUTION", Some("bookworm"), Some("trixie")),
+            ("MY_VARIABLE", Some("1"), None),
+            ("OTHER_VARIABLE", None, Some("2")),
+        ]);
+    }
+
+    #[test]
+    fn values_round_trip() {
+        let this = config(&[("LB_ARCHITECTURE", "arm64"), ("LB_SECURITY", "false"), ("MY_VARIABLE", "a \"b\" $c")]);
+        let other = LbConfig::from_values(&this.values()).unwrap();
+        assert!(this.diff(&other).is_empty());
+        assert!(!other.chroot.security);
+    }
+}
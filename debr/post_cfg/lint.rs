@@ -0,0 +1,38 @@
+use std::collections::HashSet;
+use std::error::Error;
+use ordermap::OrderSet;
+
+use colored::*;
+
+// packages requested by `include` or extras, which are purged as well
+pub fn overlaps(includes: &OrderSet<String>, from_extras: &OrderSet<String>, purge: &OrderSet<String>) -> Vec<String> {
+    let purged: HashSet<&str> = purge.iter().map(|p| package_name(p)).collect();
+    let mut findings = Vec::new();
+    for (source, packages) in [("include", includes), ("extras", from_extras)] {
+        let mut overlapping: Vec<&String> = packages.iter().filter(|p| purged.contains(package_name(p))).collect();
+        overlapping.sort();
+        for package in overlapping {
+            findings.push(format!("`{}` is in `{}` and in `purge`", package, source));
+        }
+    }
+    findings
+}
+
+// warnings by default, an error in strict mode
+pub fn report(findings: &[String], strict: bool) -> Result<(), Box<dyn Error>> {
+    if findings.is_empty() {
+        return Ok(());
+    }
+    if strict {
+        return Err(format!("Lint failed (strict mode):\n{}", findings.join("\n")).into());
+    }
+    for finding in findings {
+        eprintln!("{}", format!("W: {}", finding).yellow());
+    }
+    Ok(())
+}
+
+// `name` of `name:arch`, `name=version` or `name/release`
+pub fn package_name(package: &str) -> &str {
+    package.split([':', '=', '/']).next().unwrap_or(package)
+}
@@ -0,0 +1,61 @@
+use std::error::Error;
+
+use super::json_cfg::{Mirrors, SnapshotSpec};
+use super::lb_config::LbConfig;
+use super::snapshot;
+
+// sets the configured mirrors, the chroot mirror defaults to the bootstrap one
+pub fn apply(mirrors: &Mirrors, snapshot: Option<&SnapshotSpec>, lb_config: &mut LbConfig) -> Result<(), Box<dyn Error>> {
+    check(mirrors, snapshot)?;
+    let bootstrap = &mut lb_config.bootstrap;
+    let chroot = mirrors.chroot.as_ref().or(mirrors.bootstrap.as_ref());
+    for (mirror, fields) in [
+        (mirrors.bootstrap.as_ref(), [&mut bootstrap.mirror_bootstrap, &mut bootstrap.parent_mirror_bootstrap]),
+        (chroot, [&mut bootstrap.mirror_chroot, &mut bootstrap.parent_mirror_chroot]),
+        (mirrors.security.as_ref(), [&mut bootstrap.mirror_chroot_security, &mut bootstrap.parent_mirror_chroot_security]),
+        (mirrors.binary.as_ref(), [&mut bootstrap.mirror_binary, &mut bootstrap.parent_mirror_binary]),
+        (mirrors.binary_security.as_ref(), [&mut bootstrap.mirror_binary_security, &mut bootstrap.parent_mirror_binary_security]),
+    ] {
+        if let Some(mirror) = mirror {
+            for field in fields {
+                *field = mirror.clone();
+            }
+        }
+    }
+    // only used while building, live-build doesn't keep it in the image
+    if let Some(proxy) = &mirrors.apt_proxy {
+        lb_config.common.apt_http_proxy = proxy.clone();
+    }
+    Ok(())
+}
+
+fn check(mirrors: &Mirrors, snapshot: Option<&SnapshotSpec>) -> Result<(), Box<dyn Error>> {
+    let urls = [
+        ("bootstrap", &mirrors.bootstrap),
+        ("chroot", &mirrors.chroot),
+        ("security", &mirrors.security),
+        ("binary", &mirrors.binary),
+        ("binarySecurity", &mirrors.binary_security),
+    ];
+    for (name, url) in urls {
+        let Some(url) = url else { continue };
+        if !["http://", "https://", "file:/"].iter().any(|scheme| url.starts_with(scheme)) || url.contains(char::is_whitespace) {
+            return Err(format!("Invalid mirror `{}` for `mirrors.{}`, expected a http(s):// or file:/ URL", url, name).into());
+        }
+        // the snapshot replaces the build mirrors, and the image's ones unless it's restored
+        if let Some(spec) = snapshot {
+            if !name.starts_with("binary") || !snapshot::restore(spec) {
+                return Err(format!("`mirrors.{}` conflicts with `snapshot`, which sets this mirror already", name).into());
+            }
+        }
+    }
+    if let Some(proxy) = &mirrors.apt_proxy {
+        if !(proxy.starts_with("http://") || proxy.starts_with("https://")) || proxy.contains(char::is_whitespace) {
+            return Err(format!("Invalid `mirrors.aptProxy` `{}`, expected e.g. `http://apt-cacher:3142`", proxy).into());
+        }
+    }
+    if mirrors.offline_port == Some(0) {
+        return Err("Invalid `mirrors.offlinePort` 0, expected a port to serve the apt mirror on".into());
+    }
+    Ok(())
+}
@@ -0,0 +1,93 @@
+use std::error::Error;
+use std::fs::{self, create_dir_all, write};
+use std::io;
+use std::path::Path;
+use ordermap::OrderMap;
+
+use super::json_cfg::Package;
+
+const STAGES: [&str; 3] = ["chroot", "binary", "both"];
+
+// packages grouped by list file, each written to config/package-lists/debr_<list>.list[.<stage>]
+#[derive(Default)]
+pub struct PackageLists {
+    // file name => architectures (empty for all) => packages
+    files: OrderMap<String, OrderMap<Vec<String>, Vec<String>>>,
+}
+
+impl PackageLists {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, package: &Package) -> Result<(), Box<dyn Error>> {
+        let name = &package.name;
+        if name.is_empty() || name.contains(char::is_whitespace) || name.starts_with('#') {
+            return Err(format!("Invalid package `{}` in `include`", name).into());
+        }
+        let stage = package.stage.as_deref().unwrap_or("chroot");
+        let suffix = match stage {
+            "chroot" => ".chroot",
+            "binary" => ".binary",
+            "both" => "",
+            _ => {
+                return Err(format!(
+                    "Invalid stage `{}` for package {} (Allowed values: {})",
+                    stage, name,
+                    STAGES.iter().map(|s| format!("`{}`", s)).collect::<Vec<String>>().join(", ")
+                ).into());
+            }
+        };
+        let list = package.list.as_deref().unwrap_or("debr");
+        if list.is_empty() || !list.chars().all(|c| c.is_ascii_alphanumeric() || "-_".contains(c)) {
+            return Err(format!("Invalid list `{}` for package {}", list, name).into());
+        }
+        let mut architectures = package.arch.clone().unwrap_or_default();
+        if architectures.iter().any(|a| a.is_empty() || a.contains(char::is_whitespace)) {
+            return Err(format!("Invalid `arch` for package {}", name).into());
+        }
+        architectures.sort();
+        architectures.dedup();
+
+        let packages = self.files
+            .entry(format!("debr_{}.list{}", list, suffix))
+            .or_default()
+            .entry(architectures)
+            .or_default();
+        if !packages.contains(name) {
+            packages.push(name.clone());
+        }
+        Ok(())
+    }
+
+    // replaces the lists written by a previous run
+    pub fn place(&self, live_dir: &Path) -> io::Result<()> {
+        let lists_dir = live_dir.join("config/package-lists");
+        create_dir_all(&lists_dir)?;
+        for entry in fs::read_dir(&lists_dir)? {
+            let path = entry?.path();
+            if path.file_name().is_some_and(|n| n.to_string_lossy().starts_with("debr_")) {
+                fs::remove_file(path)?;
+            }
+        }
+
+        for (file, groups) in &self.files {
+            let mut content = String::new();
+            for (architectures, packages) in groups {
+                // live-build conditionals compare against LB_<VARIABLE> of config/
+                if !architectures.is_empty() {
+                    content.push_str(&format!("#if ARCHITECTURE {}\n", architectures.join(" ")));
+                }
+                for package in packages {
+                    content.push_str(package);
+                    content.push('\n');
+                }
+                if !architectures.is_empty() {
+                    content.push_str("#endif\n");
+                }
+            }
+            write(lists_dir.join(file), content)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, create_dir_all};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use ordermap::OrderSet;
+use reqwest::blocking::{Client, ClientBuilder};
+use reqwest::StatusCode;
+use sha2::{Digest, Sha256};
+
+use super::downloads::file_sha256;
+use super::store::hex_encode;
+
+// compressions tried in order, with the command to decompress them
+const COMPRESSIONS: [(&str, Option<&str>); 3] = [(".xz", Some("xz")), (".gz", Some("gzip")), ("", None)];
+
+// an apt repository, a suite ending with `/` is a flat repository
+pub struct Source {
+    pub url: String,
+    pub suite: String,
+    pub components: Vec<String>,
+}
+
+impl Source {
+    // `<url> <suite> [components...]` as in sources.list
+    pub fn parse(line: &str) -> Result<Source, Box<dyn Error>> {
+        let mut parts = line.split_whitespace().filter(|p| !p.starts_with('['));
+        let (Some(url), Some(suite)) = (parts.next(), parts.next()) else {
+            return Err(format!("Invalid apt source `{}`, expected `<url> <suite> [components...]`", line).into());
+        };
+        Ok(Source {
+            url: url.trim_end_matches('/').to_string(),
+            suite: suite.to_string(),
+            components: parts.map(|c| c.to_string()).collect(),
+        })
+    }
+
+    // directory of the Release file relative to the url, the indices are listed relative to it
+    fn release_dir(&self) -> String {
+        if self.suite.ends_with('/') {
+            return self.suite.trim_start_matches("./").to_string();
+        }
+        format!("dists/{}/", self.suite)
+    }
+
+    // paths of the Packages indices relative to the url, without compression suffix
+    fn indices(&self, architecture: &str) -> Vec<String> {
+        if self.suite.ends_with('/') {
+            return vec![format!("{}Packages", self.release_dir())];
+        }
+        let mut indices = Vec::new();
+        for component in &self.components {
+            for arch in [architecture, "all"] {
+                indices.push(format!("dists/{}/{}/binary-{}/Packages", self.suite, component, arch));
+            }
+        }
+        indices
+    }
+}
+
+// a binary package of a Packages index
+pub struct Stanza {
+    pub version: String,
+    pub architecture: String,
+    pub filename: String,
+    pub sha256: String,
+    pub priority: String,
+    pub section: String,
+    // `Pre-Depends` and `Depends`, one entry per group of alternatives
+    pub depends: Vec<String>,
+    pub recommends: Vec<String>,
+    // position of the index's source, as passed to `parse`
+    pub origin: usize,
+}
+
+// every version of the packages of all indices, and the providers of virtual packages
+#[derive(Default)]
+pub struct Index {
+    pub packages: HashMap<String, Vec<Stanza>>,
+    pub providers: HashMap<String, Vec<String>>,
+    // names in the order of the indices
+    pub names: OrderSet<String>,
+}
+
+impl Index {
+    // reads the indices of the sources from a local mirror directory or the cache, downloading missing ones unless `offline`
+    pub fn load(sources: &[Source], architecture: &str, mirror_dir: Option<&Path>, cache_dir: &Path, offline: bool) -> Result<Index, Box<dyn Error>> {
+        let client = ClientBuilder::new().timeout(None).build()?;
+        create_dir_all(cache_dir)?;
+        let mut index = Index::default();
+        for (position, source) in sources.iter().enumerate() {
+            // the local mirror replaces the first (distribution) source only
+            let mirror_dir = mirror_dir.filter(|_| position == 0);
+            // cached indices are revalidated against the current Release file
+            let release = match mirror_dir.is_none() && !offline {
+                true => release(&client, source)?,
+                false => HashMap::new(),
+            };
+            let mut found = false;
+            for path in source.indices(architecture) {
+                let content = match mirror_dir {
+                    Some(mirror_dir) => read_local(&mirror_dir.join(&path))?,
+                    None => {
+                        let listed = |suffix: &str| release.get(&format!("{}{}", path, suffix).replacen(&source.release_dir(), "", 1));
+                        fetch(&client, &source.url, &path, listed, cache_dir, offline)?
+                    }
+                };
+                if let Some(content) = content {
+                    index.parse(&content, position);
+                    found = true;
+                }
+            }
+            if !found && offline {
+                return Err(format!("No Packages index cached for `{} {}` ({}), run `debr fetch` first", source.url, source.suite, architecture).into());
+            }
+            if !found {
+                return Err(format!("No Packages index found for `{} {}` ({})", source.url, source.suite, architecture).into());
+            }
+        }
+        Ok(index)
+    }
+
+    // adds the stanzas of a Packages index, `origin` tells the caller where it came from
+    pub fn parse(&mut self, content: &str, origin: usize) {
+        for paragraph in content.split("\n\n") {
+            let mut fields: HashMap<&str, &str> = HashMap::new();
+            for line in paragraph.lines().filter(|line| !line.starts_with(char::is_whitespace)) {
+                if let Some((key, value)) = line.split_once(':') {
+                    fields.insert(key, value.trim());
+                }
+            }
+            let Some(name) = fields.get("Package").map(|name| name.to_string()) else {
+                continue;
+            };
+            let field = |key: &str| fields.get(key).map(|value| value.to_string()).unwrap_or_default();
+            let groups = |key: &str| -> Vec<String> {
+                fields.get(key).into_iter().flat_map(|value| value.split(',')).map(|group| group.trim().to_string()).collect()
+            };
+            // `a (= 1.0), b`
+            for provided in groups("Provides") {
+                let provided = dependency_name(&provided).to_string();
+                let providers = self.providers.entry(provided).or_default();
+                if !providers.contains(&name) {
+                    providers.push(name.clone());
+                }
+            }
+            let mut depends = groups("Pre-Depends");
+            depends.extend(groups("Depends"));
+            let stanza = Stanza {
+                version: field("Version"),
+                architecture: field("Architecture"),
+                filename: field("Filename"),
+                sha256: field("SHA256"),
+                priority: field("Priority"),
+                section: field("Section"),
+                depends,
+                recommends: groups("Recommends"),
+                origin,
+            };
+            let versions = self.packages.entry(name.clone()).or_default();
+            if !versions.iter().any(|s| s.filename == stanza.filename) {
+                versions.push(stanza);
+            }
+            self.names.insert(name);
+        }
+    }
+
+    // the package itself or the first provider of a virtual package
+    pub fn candidate(&self, name: &str) -> Option<String> {
+        if self.packages.contains_key(name) {
+            return Some(name.to_string());
+        }
+        self.providers.get(name).and_then(|providers| providers.first()).cloned()
+    }
+
+    // `None` if the package (and version, for `name=version`) exists
+    pub fn check(&self, package: &str) -> Option<String> {
+        // live-build package lists may contain globs, those are resolved by apt
+        if package.contains(['*', '?', '[']) {
+            return None;
+        }
+        let (name, version) = match package.split_once('=') {
+            Some((name, version)) => (name, Some(version)),
+            None => (package, None),
+        };
+        let name = name.split([':', '/']).next().unwrap_or(name);
+        match (self.packages.get(name), version) {
+            (Some(stanzas), Some(version)) if !stanzas.iter().any(|s| s.version == version) => {
+                let mut available: Vec<&str> = stanzas.iter().map(|s| s.version.as_str()).collect();
+                available.sort();
+                available.dedup();
+                Some(format!("`{}`: version {} not found (available: {})", package, version, available.join(", ")))
+            }
+            (Some(_), _) => None,
+            (None, _) if self.providers.contains_key(name) => None,
+            (None, _) => {
+                let suggestions = self.suggestions(name);
+                if suggestions.is_empty() {
+                    Some(format!("`{}`: not found", package))
+                } else {
+                    Some(format!("`{}`: not found, did you mean {}?", package, suggestions.join(", ")))
+                }
+            }
+        }
+    }
+
+    // up to three names close to the given one
+    fn suggestions(&self, name: &str) -> Vec<String> {
+        let max_distance = (name.len() / 3).max(2);
+        let mut candidates: Vec<(usize, &String)> = self.names.iter()
+            .filter(|candidate| candidate.len().abs_diff(name.len()) <= max_distance)
+            .map(|candidate| (strsim::levenshtein(name, candidate), candidate))
+            .filter(|(distance, _)| *distance <= max_distance)
+            .collect();
+        candidates.sort();
+        candidates.into_iter().take(3).map(|(_, candidate)| format!("`{}`", candidate)).collect()
+    }
+}
+
+// `name` of `name:any (>= 1.0) [amd64]`
+pub fn dependency_name(dependency: &str) -> &str {
+    let name = dependency.split_whitespace().next().unwrap_or_default();
+    name.split([':', '(']).next().unwrap_or(name)
+}
+
+fn read_local(path: &Path) -> io::Result<Option<String>> {
+    for (suffix, decompressor) in COMPRESSIONS {
+        let file = PathBuf::from(format!("{}{}", path.display(), suffix));
+        if file.exists() {
+            return decompress(&file, decompressor).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+// sha256 by path of the files listed in the InRelease or Release file of the source, empty if it has neither
+fn release(client: &Client, source: &Source) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    for name in ["InRelease", "Release"] {
+        let release_url = format!("{}/{}{}", source.url, source.release_dir(), name);
+        let response = client.get(&release_url).send()?;
+        if response.status() == StatusCode::NOT_FOUND || response.status() == StatusCode::FORBIDDEN {
+            continue;
+        }
+        if !response.status().is_success() {
+            return Err(format!("Failed to download {}: {}", release_url, response.status()).into());
+        }
+        return Ok(release_sha256(&response.text()?));
+    }
+    Ok(HashMap::new())
+}
+
+// ` <sha256> <size> <path>` below `SHA256:` of a Release file
+pub fn release_sha256(release: &str) -> HashMap<String, String> {
+    release.lines()
+        .skip_while(|line| *line != "SHA256:")
+        .skip(1)
+        .take_while(|line| line.starts_with(' '))
+        .filter_map(|line| match line.split_whitespace().collect::<Vec<&str>>()[..] {
+            [sha256, _, path] => Some((path.to_string(), sha256.to_string())),
+            _ => None,
+        })
+        .collect()
+}
+
+// cached by url and refreshed when the Release file lists another sha256, a missing index is `None`
+fn fetch<'a>(
+    client: &Client, url: &str, path: &str, listed: impl Fn(&str) -> Option<&'a String>, cache_dir: &Path, offline: bool,
+) -> Result<Option<String>, Box<dyn Error>> {
+    for (suffix, decompressor) in COMPRESSIONS {
+        let file_url = format!("{}/{}{}", url, path, suffix);
+        let cached = cache_dir.join(file_url.split("://").last().unwrap_or(&file_url).replace('/', "_"));
+        let sha256 = listed(suffix);
+        let outdated = match sha256 {
+            Some(sha256) => cached.exists() && file_sha256(&cached)? != *sha256,
+            None => false,
+        };
+        if !cached.exists() || outdated {
+            if offline {
+                continue;
+            }
+            let response = client.get(&file_url).send()?;
+            if response.status() == StatusCode::NOT_FOUND || response.status() == StatusCode::FORBIDDEN {
+                continue;
+            }
+            if !response.status().is_success() {
+                return Err(format!("Failed to download {}: {}", file_url, response.status()).into());
+            }
+            let content = response.bytes()?;
+            if sha256.is_some_and(|sha256| hex_encode(&Sha256::digest(&content)) != *sha256) {
+                return Err(format!("sha256 mismatch for {}, the mirror may be syncing, try again later", file_url).into());
+            }
+            println!("Fetched package index: {}", file_url);
+            fs::write(&cached, content)?;
+        }
+        return Ok(Some(decompress(&cached, decompressor)?));
+    }
+    Ok(None)
+}
+
+pub fn decompress(path: &Path, decompressor: Option<&str>) -> io::Result<String> {
+    let Some(decompressor) = decompressor else {
+        return fs::read_to_string(path);
+    };
+    let output = Command::new(decompressor)
+        .arg("-dc")
+        .arg(path)
+        .stderr(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!("Failed to decompress {}", path.display())));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACKAGES: &str = "Package: bash
+Version: 5.2.15-2+b7
+Architecture: amd64
+Pre-Depends: libc6 (>= 2.36), libtinfo6 (>= 6)
+Depends: base-files (>= 2.1.12), debianutils (>= 5.6-0.1)
+Recommends: bash-completion
+Filename: pool/main/b/bash/bash_5.2.15-2+b7_amd64.deb
+SHA256: 0123
+Priority: required
+Section: shells
+
+Package: mawk
+Version: 1.3.4.20200120-3.1
+Provides: awk
+Filename: pool/main/m/mawk/mawk_1.3.4.20200120-3.1_amd64.deb
+Description: a pattern scanning and text processing language
+ Mawk is an interpreter for the AWK Programming Language.
+";
+
+    #[test]
+    fn dependency_names() {
+        assert_eq!(dependency_name("libc6 (>= 2.36)"), "libc6");
+        assert_eq!(dependency_name("python3:any (>= 3.11~)"), "python3");
+        assert_eq!(dependency_name("grub-efi-amd64-bin [amd64]"), "grub-efi-amd64-bin");
+        assert_eq!(dependency_name(" awk"), "awk");
+    }
+
+    #[test]
+    fn parse() {
+        let mut index = Index::default();
+        index.parse(PACKAGES, 1);
+        let bash = &index.packages["bash"][0];
+        assert_eq!(bash.version, "5.2.15-2+b7");
+        assert_eq!(bash.depends, ["libc6 (>= 2.36)", "libtinfo6 (>= 6)", "base-files (>= 2.1.12)", "debianutils (>= 5.6-0.1)"]);
+        assert_eq!(bash.recommends, ["bash-completion"]);
+        assert_eq!(bash.origin, 1);
+        assert_eq!(index.providers["awk"], ["mawk"]);
+        assert_eq!(index.candidate("awk").as_deref(), Some("mawk"));
+        assert_eq!(index.names.iter().collect::<Vec<_>>(), ["bash", "mawk"]);
+
+        // the same index of another suite doesn't add versions
+        index.parse(PACKAGES, 2);
+        assert_eq!(index.packages["bash"].len(), 1);
+    }
+
+    #[test]
+    fn check() {
+        let mut index = Index::default();
+        index.parse(PACKAGES, 0);
+        assert_eq!(index.check("bash"), None);
+        assert_eq!(index.check("bash=5.2.15-2+b7"), None);
+        assert_eq!(index.check("bash:amd64"), None);
+        assert_eq!(index.check("awk"), None);
+        assert_eq!(index.check("ba*"), None);
+        assert_eq!(index.check("bash=1.0").as_deref(), Some("`bash=1.0`: version 1.0 not found (available: 5.2.15-2+b7)"));
+        assert_eq!(index.check("bsah").as_deref(), Some("`bsah`: not found, did you mean `bash`?"));
+        assert_eq!(index.check("firefox").as_deref(), Some("`firefox`: not found"));
+    }
+}
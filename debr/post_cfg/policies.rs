@@ -0,0 +1,141 @@
+use std::error::Error;
+use std::fs::{create_dir_all, set_permissions, write};
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use serde_json::{json, Map, Value};
+
+use super::json_cfg::BrowserPolicies;
+
+const CHROME_POLICIES: &str = include_str!("../assets/policies/chrome.txt");
+const FIREFOX_POLICIES: &str = include_str!("../assets/policies/firefox.txt");
+
+const CHROME_DIRS: [&str; 2] = ["etc/opt/chrome/policies/managed", "etc/chromium/policies/managed"];
+const FIREFOX_DIR: &str = "etc/firefox/policies";
+const CHROME_WEBSTORE_UPDATE_URL: &str = "https://clients2.google.com/service/update2/crx";
+
+const FEATURES: [&str; 6] = ["passwordManager", "telemetry", "sync", "privateBrowsing", "devTools", "defaultBrowserCheck"];
+
+// managed policies for Chrome/Chromium and Firefox
+#[derive(Default)]
+pub struct Policies {
+    chrome: Map<String, Value>,
+    firefox: Map<String, Value>,
+}
+
+impl Policies {
+    pub fn from_config(config: &BrowserPolicies) -> Result<Self, Box<dyn Error>> {
+        let mut policies = Self::default();
+
+        if let Some(homepage) = &config.homepage {
+            policies.set_chrome("HomepageLocation", json!(homepage))?;
+            policies.set_chrome("HomepageIsNewTabPage", json!(false))?;
+            policies.set_chrome("ShowHomeButton", json!(true))?;
+            policies.set_chrome("RestoreOnStartup", json!(4))?;
+            policies.set_chrome("RestoreOnStartupURLs", json!([homepage]))?;
+            policies.set_firefox("Homepage", json!({"URL": homepage, "StartPage": "homepage"}))?;
+        }
+
+        if let Some(extensions) = &config.extensions {
+            if let Some(chrome) = &extensions.chrome {
+                let forcelist: Vec<String> = chrome.iter()
+                    .map(|id| if id.contains(';') { id.clone() } else { format!("{};{}", id, CHROME_WEBSTORE_UPDATE_URL) })
+                    .collect();
+                policies.set_chrome("ExtensionInstallForcelist", json!(forcelist))?;
+            }
+            if let Some(firefox) = &extensions.firefox {
+                let settings: Map<String, Value> = firefox.iter()
+                    .map(|(id, url)| (id.clone(), json!({"installation_mode": "force_installed", "install_url": url})))
+                    .collect();
+                policies.set_firefox("ExtensionSettings", Value::Object(settings))?;
+            }
+        }
+
+        if let Some(bookmarks) = &config.bookmarks {
+            let chrome: Vec<Value> = bookmarks.iter().map(|b| json!({"name": b.name, "url": b.url})).collect();
+            let firefox: Vec<Value> = bookmarks.iter().map(|b| json!({"Title": b.name, "URL": b.url, "Placement": "toolbar"})).collect();
+            policies.set_chrome("ManagedBookmarks", json!(chrome))?;
+            policies.set_chrome("BookmarkBarEnabled", json!(true))?;
+            policies.set_firefox("Bookmarks", json!(firefox))?;
+            policies.set_firefox("DisplayBookmarksToolbar", json!("always"))?;
+        }
+
+        for feature in config.disabled.iter().flatten() {
+            let ((chrome, chrome_value), (firefox, firefox_value)) = disable(feature).ok_or_else(|| format!(
+                "Unknown feature `{}` in `browserPolicies.disabled` (Allowed values: {})",
+                feature,
+                FEATURES.iter().map(|name| format!("`{}`", name)).collect::<Vec<String>>().join(", ")
+            ))?;
+            policies.set_chrome(chrome, chrome_value)?;
+            policies.set_firefox(firefox, firefox_value)?;
+        }
+
+        for (name, value) in config.chrome.iter().flatten() {
+            policies.set_chrome(name, value.clone())?;
+        }
+        for (name, value) in config.firefox.iter().flatten() {
+            policies.set_firefox(name, value.clone())?;
+        }
+        Ok(policies)
+    }
+
+    fn set_chrome(&mut self, name: &str, value: Value) -> Result<(), Box<dyn Error>> {
+        set(&mut self.chrome, CHROME_POLICIES, "chrome", name, value)
+    }
+
+    fn set_firefox(&mut self, name: &str, value: Value) -> Result<(), Box<dyn Error>> {
+        set(&mut self.firefox, FIREFOX_POLICIES, "firefox", name, value)
+    }
+
+    pub fn place(&self, includes_dir: &Path) -> io::Result<()> {
+        if !self.chrome.is_empty() {
+            let content = serde_json::to_string_pretty(&self.chrome)?;
+            for dir in CHROME_DIRS {
+                let path = includes_dir.join(dir).join("debr.json");
+                place_file(&path, &content)?;
+            }
+        }
+        if !self.firefox.is_empty() {
+            let content = serde_json::to_string_pretty(&json!({"policies": self.firefox}))?;
+            place_file(&includes_dir.join(FIREFOX_DIR).join("policies.json"), &content)?;
+        }
+        Ok(())
+    }
+}
+
+type Policy = (&'static str, Value);
+
+// (chrome policy, value), (firefox policy, value) disabling a feature
+fn disable(feature: &str) -> Option<(Policy, Policy)> {
+    match feature {
+        "passwordManager" => Some((("PasswordManagerEnabled", json!(false)), ("PasswordManagerEnabled", json!(false)))),
+        "telemetry" => Some((("MetricsReportingEnabled", json!(false)), ("DisableTelemetry", json!(true)))),
+        "sync" => Some((("SyncDisabled", json!(true)), ("DisableFirefoxAccounts", json!(true)))),
+        "privateBrowsing" => Some((("IncognitoModeAvailability", json!(1)), ("DisablePrivateBrowsing", json!(true)))),
+        "devTools" => Some((("DeveloperToolsAvailability", json!(2)), ("DisableDeveloperTools", json!(true)))),
+        "defaultBrowserCheck" => Some((("DefaultBrowserSettingEnabled", json!(false)), ("DontCheckDefaultBrowser", json!(true)))),
+        _ => None,
+    }
+}
+
+fn set(policies: &mut Map<String, Value>, known: &str, browser: &str, name: &str, value: Value) -> Result<(), Box<dyn Error>> {
+    if !known.lines().any(|known_name| known_name == name) {
+        return Err(format!("Unknown {} policy `{}` in `browserPolicies`", browser, name).into());
+    }
+    if let Some(existing) = policies.get(name) {
+        if existing != &value {
+            return Err(format!(
+                "Conflict in `browserPolicies` for {} policy `{}`\nThisValue:\n{}\nOtherValue:\n{}",
+                browser, name, existing, value
+            ).into());
+        }
+    }
+    policies.insert(name.to_string(), value);
+    Ok(())
+}
+
+fn place_file(path: &Path, content: &str) -> io::Result<()> {
+    create_dir_all(path.parent().unwrap())?;
+    write(path, format!("{}\n", content))?;
+    set_permissions(path, PermissionsExt::from_mode(0o644))
+}
@@ -5,6 +5,7 @@ use std::path::Path;
 use reqwest::blocking::ClientBuilder;
 
 pub fn place_key(url: &str, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    // reqwest picks up http_proxy, https_proxy and no_proxy from the environment
     let client = ClientBuilder::new()
         .https_only(true)
         .build()?;
@@ -19,8 +20,7 @@ pub fn place_key(url: &str, path: &Path) -> Result<(), Box<dyn std::error::Error
     let response = client.get(url).send()?;
 
     if !response.status().is_success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
+        return Err(io::Error::other(
             format!("Failed to download {}", url),
         )
         .into());
@@ -65,7 +65,7 @@ fn dearmor_to(input: &[u8], output_path: &Path) -> Result<(), Box<dyn std::error
         .write(true)
         .create(do_create)
         .truncate(true)
-        .open(&output_path)?;
+        .open(output_path)?;
     file.write_all(&output.stdout)?;
     Ok(())
 }
\ No newline at end of file
@@ -1,68 +1,203 @@
 use std::process::{Command, Stdio};
-use std::env;
 use std::io;
 use std::fs::{self, create_dir_all};
 use std::path::Path;
+use std::thread;
+use ordermap::{OrderMap, OrderSet};
 
-pub fn download(package: &str, architecture: &str, target_dir: &Path) -> io::Result<()> {
-    env::set_var("UBUNTU_STORE_ARCH", architecture);
+use super::json_cfg::Snap;
+use super::store::Store;
 
-    // Ensure the target directory exists
-    if !target_dir.exists() {
-        create_dir_all(target_dir)?;
+const CONFINEMENTS: [&str; 3] = ["strict", "classic", "devmode"];
+// sha3-384 of the key signing the generic models
+const GENERIC_KEY: &str = "d-JcZF9nD9eBw7bwMnH61x-bklnQOhQud1Is6o_cn2wTj8EYDi9musrIT9z2MdAa";
+
+pub fn validate(snap: &Snap) -> io::Result<()> {
+    if let Some(confinement) = &snap.confinement {
+        if !CONFINEMENTS.contains(&confinement.as_str()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid confinement `{}` for snap {} (Allowed values: `strict`, `classic`, `devmode`)", confinement, snap.name),
+            ));
+        }
     }
+    Ok(())
+}
 
-    // snap download
-    let output = Command::new("snap")
-        .arg("download")
-        .arg(package)
-        .env("UBUNTU_STORE_ARCH", architecture)
-        .current_dir(target_dir)
-        .stdout(Stdio::inherit()) 
-        .stderr(Stdio::inherit()) 
-        .stdin(Stdio::inherit())
-        .output()?;
+// a snap placed in the seed
+pub struct Seeded {
+    pub snap: Snap,
+    pub revision: String,
+    pub file: String,
+    pub meta: SnapMeta,
+}
 
-    if !output.status.success() {
-        let err_msg = String::from_utf8_lossy(&output.stderr);
-        return Err(io::Error::new(io::ErrorKind::Other, format!("Failed to download {}: {}", package, err_msg)));
+// the parts of meta/snap.yaml needed to resolve dependencies
+#[derive(Default)]
+pub struct SnapMeta {
+    pub kind: String,
+    pub base: Option<String>,
+    pub default_providers: Vec<String>,
+}
+
+impl SnapMeta {
+    // snaps required to be seeded before this one
+    pub fn dependencies(&self) -> Vec<String> {
+        let mut dependencies = Vec::new();
+        match (&self.base, self.kind.as_str()) {
+            (Some(base), _) if base != "none" => dependencies.push(base.clone()),
+            // apps without base run on `core`
+            (None, "app") => dependencies.push(String::from("core")),
+            _ => {}
+        }
+        for provider in &self.default_providers {
+            if !dependencies.contains(provider) {
+                dependencies.push(provider.clone());
+            }
+        }
+        dependencies
     }
+}
+
+// downloads the requested snaps with their bases and default content providers, dependencies first
+pub fn resolve(requested: &OrderMap<String, Snap>, store: &Store, seed_dir: &Path) -> io::Result<Vec<Seeded>> {
+    let mut seeded: OrderMap<String, Seeded> = OrderMap::new();
+
+    // snapd is always seeded first
+    let snapd = requested.get("snapd").cloned().unwrap_or(Snap { name: String::from("snapd"), ..Default::default() });
+    let mut wave: Vec<Snap> = vec![snapd];
+    wave.extend(requested.values().filter(|snap| snap.name != "snapd").cloned());
 
-    let assert_file_pattern = format!("{}_", package);
-    let snap_file_pattern = format!("{}_", package);
+    // each wave is downloaded in parallel, the next one consists of their missing dependencies
+    while !wave.is_empty() {
+        let results: Vec<io::Result<Seeded>> = thread::scope(|scope| {
+            let handles: Vec<_> = wave.iter().map(|snap| scope.spawn(|| download(snap, store, seed_dir))).collect();
+            handles.into_iter().map(|handle| handle.join().unwrap_or_else(|_| Err(io::Error::other("Snap download thread panicked")))).collect()
+        });
 
-    let mut assert_file = None;
-    let mut snap_file = None;
+        let mut next: Vec<Snap> = Vec::new();
+        for result in results {
+            let entry = result?;
+            for dependency in entry.meta.dependencies() {
+                if !seeded.contains_key(&dependency) && !wave.iter().chain(next.iter()).any(|snap| snap.name == dependency) {
+                    println!("Resolved snap {} required by {}", dependency, entry.snap.name);
+                    next.push(requested.get(&dependency).cloned().unwrap_or(Snap { name: dependency, ..Default::default() }));
+                }
+            }
+            seeded.insert(entry.snap.name.clone(), entry);
+        }
+        wave = next;
+    }
 
-    // find the downloaded files
-    for entry in fs::read_dir(target_dir)? {
-        let entry = entry?;
-        let file_name = entry.file_name().to_string_lossy().to_string();
+    // order dependencies before their dependents
+    let mut ordered: OrderSet<String> = OrderSet::new();
+    let mut visiting: OrderSet<String> = OrderSet::new();
+    let names: Vec<String> = seeded.keys().cloned().collect();
+    for name in &names {
+        visit(name, &seeded, &mut ordered, &mut visiting);
+    }
+    Ok(ordered.iter().filter_map(|name| seeded.swap_remove(name)).collect())
+}
 
-        // Check for .assert file that matches the prefix
-        if file_name.starts_with(&assert_file_pattern) && file_name.ends_with(".assert") {
-            let assert_path = entry.path().canonicalize()?;
-            assert_file = Some(assert_path);
+fn visit(name: &str, seeded: &OrderMap<String, Seeded>, ordered: &mut OrderSet<String>, visiting: &mut OrderSet<String>) {
+    if ordered.contains(name) || !visiting.insert(name.to_string()) {
+        return;
+    }
+    if let Some(entry) = seeded.get(name) {
+        for dependency in entry.meta.dependencies() {
+            visit(&dependency, seeded, ordered, visiting);
         }
+    }
+    ordered.insert(name.to_string());
+}
+
+// reads meta/snap.yaml from the squashfs
+pub fn read_meta(snap_file: &Path) -> io::Result<SnapMeta> {
+    let output = Command::new("unsquashfs")
+        .arg("-cat")
+        .arg(snap_file)
+        .arg("meta/snap.yaml")
+        .stderr(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!("Failed to read meta/snap.yaml from {}", snap_file.display())));
+    }
+    Ok(parse_meta(&String::from_utf8_lossy(&output.stdout)))
+}
+
+// only top-level `type`/`base` and `default-provider` of plugs are of interest, a full YAML parser is not needed
+fn parse_meta(yaml: &str) -> SnapMeta {
+    let mut meta = SnapMeta { kind: String::from("app"), ..Default::default() };
+    let value = |line: &str| -> String {
+        let value = line.split_once(':').map(|(_, v)| v).unwrap_or_default();
+        let value = value.split(" #").next().unwrap_or_default().trim();
+        value.trim_matches(|c| c == '"' || c == '\'').to_string()
+    };
 
-        // Check for .snap file that matches the prefix
-        if file_name.starts_with(&snap_file_pattern) && file_name.ends_with(".snap") {
-            let snap_path = entry.path().canonicalize()?;
-            snap_file = Some(snap_path);
+    for line in yaml.lines() {
+        let top_level = !line.starts_with(char::is_whitespace);
+        let trimmed = line.trim();
+        if top_level && trimmed.starts_with("type:") {
+            meta.kind = value(trimmed);
+        } else if top_level && trimmed.starts_with("base:") {
+            meta.base = Some(value(trimmed));
+        } else if trimmed.starts_with("default-provider:") {
+            // `<snap>` or `<snap>:<slot>`
+            let provider = value(trimmed);
+            let provider = provider.split(':').next().unwrap_or_default().to_string();
+            if !provider.is_empty() && !meta.default_providers.contains(&provider) {
+                meta.default_providers.push(provider);
+            }
         }
     }
+    meta
+}
+
+// fetches the snap into the cache and places it into `<seed_dir>/snaps` and its assertions into `<seed_dir>/assertions`
+pub fn download(snap: &Snap, store: &Store, seed_dir: &Path) -> io::Result<Seeded> {
+    let snaps_dir = seed_dir.join("snaps");
+    let assertions_dir = seed_dir.join("assertions");
+    create_dir_all(&snaps_dir)?;
+    create_dir_all(&assertions_dir)?;
+
+    let fetched = store.fetch(snap)?;
+    let file_name = format!("{}_{}.snap", snap.name, fetched.revision);
+    fs::copy(&fetched.path, snaps_dir.join(&file_name))?;
+    fs::write(assertions_dir.join(format!("{}.assert", snap.name)), &fetched.assertions)?;
+    let meta = read_meta(&fetched.path)?;
+
+    println!("Downloaded snap: {} (revision {})", snap.name, fetched.revision);
+    Ok(Seeded { snap: snap.clone(), revision: fetched.revision, file: file_name, meta })
+}
 
-    // Ensure both files are found
-    if let (Some(assert_file), Some(snap_file)) = (assert_file, snap_file) {
-        let snap_path = target_dir.join(format!("{}.snap", package));
-        let assert_path = target_dir.join(format!("{}.assert", package));
+// model, account and account-key assertions for generic classic seeding
+pub fn place_model(store: &Store, seed_dir: &Path) -> io::Result<()> {
+    let assertions_dir = seed_dir.join("assertions");
+    create_dir_all(&assertions_dir)?;
 
-        fs::rename(snap_file, &snap_path)?;
-        fs::rename(assert_file, &assert_path)?;
+    let assertions = [
+        ("model", store.assertion("model", &["16", "generic", "generic-classic"])?),
+        ("generic.account", store.assertion("account", &["generic"])?),
+        ("generic.account-key", store.assertion("account-key", &[GENERIC_KEY])?),
+    ];
+    for (file_name, assertion) in assertions.iter() {
+        fs::write(assertions_dir.join(file_name), assertion)?;
+    }
+    Ok(())
+}
 
-        println!("Downloaded snap: {}", package);
-        Ok(())
-    } else {
-        Err(io::Error::new(io::ErrorKind::NotFound, "Expected .assert or .snap files not found"))
+// seed.yaml, snaps in installation order
+pub fn seed_yaml(seeded: &[Seeded]) -> String {
+    let mut content = String::from("snaps:\n");
+    for entry in seeded {
+        content.push_str(&format!("  - name: {}\n", entry.snap.name));
+        content.push_str(&format!("    channel: {}\n", entry.snap.channel.clone().unwrap_or(String::from("stable"))));
+        match entry.snap.confinement.as_deref() {
+            Some("classic") => content.push_str("    classic: true\n"),
+            Some("devmode") => content.push_str("    devmode: true\n"),
+            _ => {}
+        }
+        content.push_str(&format!("    file: {}\n", entry.file));
     }
+    content
 }
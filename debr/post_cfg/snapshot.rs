@@ -0,0 +1,97 @@
+use std::error::Error;
+use std::fs::{create_dir_all, set_permissions, write};
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use super::json_cfg::SnapshotSpec;
+use super::lb_config::LbConfig;
+
+pub const SNAPSHOT_URL: &str = "https://snapshot.debian.org/archive";
+// installed by live-build into /etc/apt/apt.conf.d/ of the chroot for the build only
+const BUILD_APT_CONF: &str = "debr-snapshot.conf";
+// kept in the image if it keeps using the snapshot, under another name as live-build removes its own
+const IMAGE_APT_CONF: &str = "etc/apt/apt.conf.d/80debr-snapshot";
+// the Release files of a snapshot are expired
+const CHECK_VALID_UNTIL: &str = "Acquire::Check-Valid-Until \"false\";\n";
+
+// archive and security mirror of the snapshot
+pub struct Mirrors {
+    pub archive: String,
+    pub security: String,
+}
+
+// `20240101T000000Z` (or `20240101`) on snapshot.debian.org, or the base URL of a stand-in serving `debian/` and `debian-security/`
+pub fn mirrors(snapshot: &str) -> Result<Mirrors, Box<dyn Error>> {
+    if snapshot.starts_with("http://") || snapshot.starts_with("https://") || snapshot.starts_with("file:/") {
+        let base = snapshot.trim_end_matches('/');
+        return Ok(Mirrors { archive: format!("{}/debian/", base), security: format!("{}/debian-security/", base) });
+    }
+    let bytes = snapshot.as_bytes();
+    let valid = match bytes.len() {
+        8 => bytes.iter().all(u8::is_ascii_digit),
+        16 => bytes[..8].iter().all(u8::is_ascii_digit)
+            && bytes[8] == b'T'
+            && bytes[9..15].iter().all(u8::is_ascii_digit)
+            && bytes[15] == b'Z',
+        _ => false,
+    };
+    if !valid {
+        return Err(format!("Invalid snapshot `{}`, expected a timestamp like `20240101T000000Z` or a URL", snapshot).into());
+    }
+    Ok(Mirrors {
+        archive: format!("{}/debian/{}/", SNAPSHOT_URL, snapshot),
+        security: format!("{}/debian-security/{}/", SNAPSHOT_URL, snapshot),
+    })
+}
+
+// points the build at the snapshot, the image keeps it unless `restore` is set
+pub fn apply(spec: &SnapshotSpec, lb_config: &mut LbConfig, live_dir: &Path) -> Result<(), Box<dyn Error>> {
+    let at = match spec {
+        SnapshotSpec::At(at) => at,
+        SnapshotSpec::Snapshot(snapshot) => &snapshot.at,
+    };
+    let restore = restore(spec);
+    let mirrors = mirrors(at)?;
+    let bootstrap = &mut lb_config.bootstrap;
+    for mirror in [
+        &mut bootstrap.mirror_bootstrap, &mut bootstrap.parent_mirror_bootstrap,
+        &mut bootstrap.mirror_chroot, &mut bootstrap.parent_mirror_chroot,
+    ] {
+        *mirror = mirrors.archive.clone();
+    }
+    for mirror in [&mut bootstrap.mirror_chroot_security, &mut bootstrap.parent_mirror_chroot_security] {
+        *mirror = mirrors.security.clone();
+    }
+    if !restore {
+        for mirror in [&mut bootstrap.mirror_binary, &mut bootstrap.parent_mirror_binary] {
+            *mirror = mirrors.archive.clone();
+        }
+        for mirror in [&mut bootstrap.mirror_binary_security, &mut bootstrap.parent_mirror_binary_security] {
+            *mirror = mirrors.security.clone();
+        }
+    }
+    place_apt_conf(live_dir, !restore)?;
+    Ok(())
+}
+
+// whether the final image switches back to the regular mirrors
+pub fn restore(spec: &SnapshotSpec) -> bool {
+    match spec {
+        SnapshotSpec::At(_) => false,
+        SnapshotSpec::Snapshot(snapshot) => snapshot.restore.unwrap_or(false),
+    }
+}
+
+fn place_apt_conf(live_dir: &Path, in_image: bool) -> io::Result<()> {
+    let apt_dir = live_dir.join("config/apt");
+    create_dir_all(&apt_dir)?;
+    write(apt_dir.join(BUILD_APT_CONF), CHECK_VALID_UNTIL)?;
+    if in_image {
+        let path = live_dir.join("config/includes.chroot_after_packages").join(IMAGE_APT_CONF);
+        create_dir_all(path.parent().unwrap())?;
+        write(&path, CHECK_VALID_UNTIL)?;
+        set_permissions(&path, PermissionsExt::from_mode(0o644))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,177 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, create_dir_all, remove_dir_all, remove_file, rename, File, FileTimes};
+use std::io;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+
+use super::downloads::file_sha256;
+
+// what `debr config` generated, to detect drift without generating again
+const MANIFEST: &str = ".debr-manifest";
+const OLD_CONFIG: &str = "config.debr-old";
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Manifest {
+    // fingerprint of what the generation depends on, see `drift::fingerprint`
+    pub inputs: String,
+    // live-build variables of config/{bootstrap,common,chroot,binary,source}
+    pub variables: BTreeMap<String, String>,
+    // generated files by path relative to the live dir
+    pub files: BTreeMap<String, Entry>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum Entry {
+    File { sha256: String, mode: u32 },
+    Symlink(String),
+}
+
+impl Entry {
+    pub fn read(path: &Path) -> io::Result<Entry> {
+        let metadata = fs::symlink_metadata(path)?;
+        if metadata.file_type().is_symlink() {
+            return Ok(Entry::Symlink(fs::read_link(path)?.to_string_lossy().to_string()));
+        }
+        Ok(Entry::File { sha256: file_sha256(path)?, mode: metadata.permissions().mode() & 0o7777 })
+    }
+}
+
+// restores the previous config if a swap was interrupted between its renames
+pub fn recover(live_dir: &Path) -> io::Result<()> {
+    let old_config = live_dir.join(OLD_CONFIG);
+    if !old_config.exists() {
+        return Ok(());
+    }
+    if live_dir.join("config").exists() {
+        remove_dir_all(old_config)
+    } else {
+        rename(old_config, live_dir.join("config"))
+    }
+}
+
+// moves the generated config from `staging` into `live_dir`, keeping files of the previous config debr doesn't own.
+// config/ is renamed aside and the new one renamed in place, `recover` undoes an interruption in between
+pub fn swap(staging: &Path, live_dir: &Path, mut manifest: Manifest) -> io::Result<()> {
+    let owned = paths(staging)?;
+    for path in &owned {
+        manifest.files.insert(path.clone(), Entry::read(&staging.join(path))?);
+    }
+
+    // without a manifest, the previous config is considered generated completely
+    let previous_owned: Option<BTreeSet<String>> = read_manifest(live_dir)?
+        .map(|previous| previous.files.into_keys().collect());
+    if let Some(previous_owned) = &previous_owned {
+        for path in paths(live_dir)?.difference(previous_owned) {
+            // next to config/ are the images and logs of the build
+            if path.starts_with("config/") && !owned.contains(path) {
+                println!("Keeping {} (not generated by debr)", path);
+                copy_entry(&live_dir.join(path), &staging.join(path))?;
+            }
+        }
+    }
+    fs::write(staging.join(MANIFEST), serde_json::to_string_pretty(&manifest)? + "\n")?;
+
+    create_dir_all(live_dir)?;
+    let config = live_dir.join("config");
+    let old_config = live_dir.join(OLD_CONFIG);
+    if config.exists() {
+        rename(&config, &old_config)?;
+    }
+    rename(staging.join("config"), &config)?;
+    // snaps.manifest, the manifest and what `lb config` creates besides config/, e.g. .build/
+    merge(staging, live_dir)?;
+    // stale files next to config/, e.g. snaps.manifest once all snaps are removed
+    for path in previous_owned.iter().flatten() {
+        if !path.starts_with("config/") && !owned.contains(path) {
+            remove_file(live_dir.join(path)).ok();
+        }
+    }
+    remove_dir_all(&old_config).ok();
+    Ok(())
+}
+
+// sets the mtime of every file and directory below `dir`, symlinks are left as they are
+pub fn set_mtimes(dir: &Path, epoch: u64) -> io::Result<()> {
+    let time = UNIX_EPOCH + Duration::from_secs(epoch);
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let file_type = fs::symlink_metadata(&path)?.file_type();
+        if file_type.is_symlink() {
+            continue;
+        }
+        if file_type.is_dir() {
+            set_mtimes(&path, epoch)?;
+        } else {
+            File::open(&path)?.set_times(FileTimes::new().set_accessed(time).set_modified(time))?;
+        }
+    }
+    File::open(dir)?.set_times(FileTimes::new().set_accessed(time).set_modified(time))
+}
+
+pub fn read_manifest(live_dir: &Path) -> io::Result<Option<Manifest>> {
+    let path = live_dir.join(MANIFEST);
+    match fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str(&content).map(Some).map_err(|e| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid {}: {}, remove it and run `debr config`", path.display(), e),
+        )),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// files and symlinks below config/ and next to it, relative to `dir`
+pub fn paths(dir: &Path) -> io::Result<BTreeSet<String>> {
+    let mut paths = BTreeSet::new();
+    if dir.exists() {
+        collect(dir, dir, &mut paths)?;
+    }
+    Ok(paths)
+}
+
+// files and symlinks below `dir` as paths relative to `root`, top-level directories other than config/ are skipped
+fn collect(root: &Path, dir: &Path, paths: &mut BTreeSet<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let relative = path.strip_prefix(root).unwrap().to_string_lossy().to_string();
+        let file_type = fs::symlink_metadata(&path)?.file_type();
+        if file_type.is_dir() {
+            if dir != root || relative == "config" {
+                collect(root, &path, paths)?;
+            }
+        } else if relative != MANIFEST {
+            paths.insert(relative);
+        }
+    }
+    Ok(())
+}
+
+// moves the entries of `from` into `to`, files are replaced, directories merged
+// so the build state live-build keeps in them (e.g. the stages done in .build/) survives
+fn merge(from: &Path, to: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(from)? {
+        let path = entry?.path();
+        let target = to.join(path.file_name().unwrap());
+        let is_dir = |path: &Path| fs::symlink_metadata(path).is_ok_and(|m| m.is_dir());
+        if is_dir(&path) && is_dir(&target) {
+            merge(&path, &target)?;
+        } else if is_dir(&path) && target.exists() {
+            continue;
+        } else {
+            rename(&path, &target)?;
+        }
+    }
+    Ok(())
+}
+
+fn copy_entry(from: &Path, to: &Path) -> io::Result<()> {
+    create_dir_all(to.parent().unwrap())?;
+    if fs::symlink_metadata(from)?.file_type().is_symlink() {
+        symlink(fs::read_link(from)?, to)
+    } else {
+        fs::copy(from, to).map(|_| ())
+    }
+}
@@ -0,0 +1,360 @@
+use std::fs::{self, create_dir_all, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use reqwest::blocking::{Client, ClientBuilder};
+use serde_json::{json, Value};
+use sha3::{Digest, Sha3_384};
+
+use super::json_cfg::Snap;
+
+pub const DEFAULT_URL: &str = "https://api.snapcraft.io";
+const ASSERTION_MIME: &str = "application/x.ubuntu.assertion";
+
+// client for the snap store API, downloads are cached by their sha3-384
+pub struct Store {
+    client: Client,
+    url: String,
+    architecture: String,
+    cache_dir: PathBuf,
+    // resolve revisions and assertions from the cache only
+    offline: bool,
+}
+
+// a snap verified and cached locally
+pub struct Fetched {
+    pub revision: String,
+    pub path: PathBuf,
+    pub assertions: String,
+}
+
+impl Store {
+    pub fn new(url: &str, architecture: &str, cache_dir: &Path, offline: bool) -> io::Result<Self> {
+        let client = ClientBuilder::new()
+            .timeout(None)
+            .build()
+            .map_err(io::Error::other)?;
+        create_dir_all(cache_dir)?;
+        Ok(Store {
+            client,
+            url: url.trim_end_matches('/').to_string(),
+            architecture: architecture.to_string(),
+            cache_dir: cache_dir.to_path_buf(),
+            offline,
+        })
+    }
+
+    // resolves the channel or revision and downloads the snap with its assertions, if not cached
+    pub fn fetch(&self, snap: &Snap) -> io::Result<Fetched> {
+        // the last resolution of the channel or revision, `<revision> <sha3-384>`
+        let pinned = match snap.revision {
+            Some(revision) => format!("r{}", revision),
+            None => snap.channel.clone().unwrap_or(String::from("stable")).replace('/', "_"),
+        };
+        let resolved_path = self.cache_dir.join(format!("{}_{}_{}.resolved", snap.name, pinned, self.architecture));
+        if self.offline {
+            let resolved = fs::read_to_string(&resolved_path).unwrap_or_default();
+            let Some((revision, sha3_384)) = resolved.trim().split_once(' ') else {
+                return Err(io::Error::other(format!("Snap {} ({}) is not cached, run `debr fetch` first", snap.name, pinned)));
+            };
+            let path = self.cache_dir.join(format!("{}.snap", sha3_384));
+            if !path.exists() || file_sha3_384(&path)? != sha3_384 {
+                return Err(io::Error::other(format!("Cached snap {} is missing or corrupt, run `debr fetch` again", snap.name)));
+            }
+            println!("Using cached snap: {} (revision {})", snap.name, revision);
+            let assertions = self.snap_assertions(&snap.name, sha3_384)?;
+            return Ok(Fetched { revision: revision.to_string(), path, assertions });
+        }
+
+        let mut action = json!({"action": "download", "instance-key": "debr", "name": snap.name});
+        if let Some(revision) = snap.revision {
+            action["revision"] = json!(revision);
+        } else {
+            action["channel"] = json!(snap.channel.clone().unwrap_or(String::from("stable")));
+        }
+        let body = json!({"context": [], "actions": [action], "fields": ["download", "revision", "snap-id"]});
+
+        let response: Value = self.client
+            .post(format!("{}/v2/snaps/refresh", self.url))
+            .header("Snap-Device-Series", "16")
+            .header("Snap-Device-Architecture", &self.architecture)
+            .json(&body)
+            .send()
+            .and_then(|r| r.error_for_status())
+            .and_then(|r| r.json())
+            .map_err(|e| io::Error::other(format!("Failed to query the snap store for {}: {}", snap.name, e)))?;
+
+        let result = &response["results"][0];
+        if result["result"] == "error" {
+            return Err(io::Error::other(format!("Snap store error for {}: {}", snap.name, result["error"]["message"])));
+        }
+        let info = &result["snap"];
+        let (Some(url), Some(sha3_384), Some(revision)) = (
+            info["download"]["url"].as_str(),
+            info["download"]["sha3-384"].as_str(),
+            info["revision"].as_u64(),
+        ) else {
+            return Err(io::Error::other(format!("Unexpected snap store response for {}", snap.name)));
+        };
+        // the digest names the cached files
+        if sha3_384.len() != 96 || !sha3_384.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(io::Error::other(format!("Invalid sha3-384 `{}` in the snap store response for {}", sha3_384, snap.name)));
+        }
+
+        let path = self.cache_dir.join(format!("{}.snap", sha3_384));
+        if path.exists() && file_sha3_384(&path)? == sha3_384 {
+            println!("Using cached snap: {} (revision {})", snap.name, revision);
+        } else {
+            self.download(&snap.name, url, &path)?;
+            let digest = file_sha3_384(&path)?;
+            if digest != sha3_384 {
+                fs::remove_file(&path).ok();
+                return Err(io::Error::other(format!("sha3-384 mismatch for {}: expected {}, got {}", snap.name, sha3_384, digest)));
+            }
+        }
+
+        let assertions = self.snap_assertions(&snap.name, sha3_384)?;
+        fs::write(&resolved_path, format!("{} {}\n", revision, sha3_384))?;
+        Ok(Fetched { revision: revision.to_string(), path, assertions })
+    }
+
+    // account-key(s), account, snap-declaration and snap-revision, verified against the file digest
+    fn snap_assertions(&self, name: &str, sha3_384: &str) -> io::Result<String> {
+        let cache_path = self.cache_dir.join(format!("{}.assert", sha3_384));
+        if let Ok(cached) = fs::read_to_string(&cache_path) {
+            return Ok(cached);
+        }
+
+        let digest = base64_url(&hex_decode(sha3_384)?);
+        let revision = self.assertion("snap-revision", &[&digest])?;
+        if header(&revision, "snap-sha3-384").as_deref() != Some(digest.as_str()) {
+            return Err(io::Error::other(format!("snap-revision assertion of {} does not match its sha3-384", name)));
+        }
+        let snap_id = header(&revision, "snap-id").ok_or_else(|| io::Error::other("snap-revision without snap-id"))?;
+        let declaration = self.assertion("snap-declaration", &["16", &snap_id])?;
+        let publisher = header(&declaration, "publisher-id").ok_or_else(|| io::Error::other("snap-declaration without publisher-id"))?;
+        let account = self.assertion("account", &[&publisher])?;
+
+        let mut keys: Vec<String> = Vec::new();
+        for signed in [&revision, &declaration, &account] {
+            if let Some(key) = header(signed, "sign-key-sha3-384") {
+                if !keys.contains(&key) {
+                    keys.push(key);
+                }
+            }
+        }
+        let mut stream: Vec<String> = Vec::new();
+        for key in keys {
+            stream.push(self.assertion("account-key", &[&key])?);
+        }
+        stream.extend([account, declaration, revision]);
+
+        let content = format!("{}\n", stream.iter().map(|a| a.trim_end()).collect::<Vec<&str>>().join("\n\n"));
+        fs::write(&cache_path, &content)?;
+        Ok(content)
+    }
+
+    // cached, so the model assertions are available offline
+    pub fn assertion(&self, kind: &str, primary_key: &[&str]) -> io::Result<String> {
+        let cache_path = self.cache_dir.join(format!("{}_{}.assert", kind, primary_key.join("_")));
+        if self.offline {
+            return fs::read_to_string(&cache_path).map_err(|_| io::Error::other(format!(
+                "{} assertion {} is not cached, run `debr fetch` first", kind, primary_key.join("/")
+            )));
+        }
+        let url = format!("{}/v2/assertions/{}/{}", self.url, kind, primary_key.join("/"));
+        let assertion = self.client
+            .get(&url)
+            .header("Accept", ASSERTION_MIME)
+            .send()
+            .and_then(|r| r.error_for_status())
+            .and_then(|r| r.text())
+            .map_err(|e| io::Error::other(format!("Failed to fetch {} assertion {}: {}", kind, primary_key.join("/"), e)))?;
+        fs::write(&cache_path, &assertion)?;
+        Ok(assertion)
+    }
+
+    fn download(&self, name: &str, url: &str, path: &Path) -> io::Result<()> {
+        let mut response = self.client
+            .get(url)
+            .send()
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| io::Error::other(format!("Failed to download {}: {}", name, e)))?;
+        let total = response.content_length().unwrap_or(0);
+
+        let partial = path.with_extension("part");
+        let mut file = File::create(&partial)?;
+        let mut buffer = vec![0u8; 1 << 16];
+        let mut downloaded: u64 = 0;
+        let mut reported = 0;
+        loop {
+            let read = response.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            file.write_all(&buffer[..read])?;
+            downloaded += read as u64;
+            if let Some(percent) = (downloaded * 100).checked_div(total) {
+                if percent >= reported + 10 {
+                    reported = percent - percent % 10;
+                    println!("[{:>3}%] {} ({} / {} MiB)", reported, name, downloaded >> 20, total >> 20);
+                }
+            }
+        }
+        file.flush()?;
+        fs::rename(&partial, path)
+    }
+}
+
+pub fn file_sha3_384(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha3_384::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(hex_encode(&hasher.finalize()))
+}
+
+// value of a header of an assertion
+fn header(assertion: &str, name: &str) -> Option<String> {
+    assertion
+        .lines()
+        .take_while(|line| !line.is_empty())
+        .find_map(|line| line.strip_prefix(&format!("{}:", name)))
+        .map(|value| value.trim().to_string())
+}
+
+pub fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hex_decode(hex: &str) -> io::Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(io::Error::other(format!("Invalid hex digest {}", hex)));
+    }
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap_or_default(), 16).map_err(|_| io::Error::other(format!("Invalid hex digest {}", hex))))
+        .collect()
+}
+
+// unpadded base64url, as used for digests in assertions
+fn base64_url(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::thread;
+
+    const SNAP: &[u8] = b"hsqs snap content";
+
+    // a stand-in for the store API, `sha3_384` is what it claims the snap's digest is
+    fn store(sha3_384: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (base, sha3_384) = (url.clone(), sha3_384.to_string());
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                reader.read_line(&mut request).unwrap();
+                let mut length = 0;
+                let mut header = String::new();
+                while reader.read_line(&mut header).unwrap() > 2 {
+                    if let Some(value) = header.to_ascii_lowercase().strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                    header.clear();
+                }
+                reader.read_exact(&mut vec![0; length]).unwrap();
+
+                let path = request.split_whitespace().nth(1).unwrap_or_default();
+                let digest = hex_decode(&sha3_384).map(|bytes| base64_url(&bytes)).unwrap_or_default();
+                let body: Vec<u8> = match path {
+                    "/v2/snaps/refresh" => json!({"results": [{"result": "download", "snap": {
+                        "revision": 42, "download": {"url": format!("{}/download/hello.snap", base), "sha3-384": sha3_384},
+                    }}]}).to_string().into_bytes(),
+                    "/download/hello.snap" => SNAP.to_vec(),
+                    _ if path.starts_with("/v2/assertions/snap-revision/") => {
+                        format!("type: snap-revision\nsnap-sha3-384: {}\nsnap-id: hello-id\nsign-key-sha3-384: store-key\n\nsignature\n", digest).into_bytes()
+                    }
+                    "/v2/assertions/snap-declaration/16/hello-id" => b"type: snap-declaration\npublisher-id: publisher\nsign-key-sha3-384: store-key\n\nsignature\n".to_vec(),
+                    "/v2/assertions/account/publisher" => b"type: account\nsign-key-sha3-384: store-key\n\nsignature\n".to_vec(),
+                    "/v2/assertions/account-key/store-key" => b"type: account-key\n\nsignature\n".to_vec(),
+                    _ => {
+                        stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").ok();
+                        continue;
+                    }
+                };
+                write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len()).unwrap();
+                stream.write_all(&body).ok();
+            }
+        });
+        url
+    }
+
+    fn sha3_384(content: &[u8]) -> String {
+        hex_encode(&Sha3_384::digest(content))
+    }
+
+    fn hello() -> Snap {
+        Snap { name: String::from("hello"), ..Default::default() }
+    }
+
+    #[test]
+    fn fetch_and_resolve_offline() {
+        let cache = tempfile::tempdir().unwrap();
+        let digest = sha3_384(SNAP);
+        let online = Store::new(&store(&digest), "amd64", cache.path(), false).unwrap();
+        let fetched = online.fetch(&hello()).unwrap();
+        assert_eq!(fetched.revision, "42");
+        assert_eq!(fs::read(&fetched.path).unwrap(), SNAP);
+        assert!(fetched.assertions.starts_with("type: account-key\n"));
+        assert_eq!(fs::read_to_string(cache.path().join("hello_stable_amd64.resolved")).unwrap(), format!("42 {}\n", digest));
+
+        // the store isn't asked again
+        let offline = Store::new("http://127.0.0.1:1", "amd64", cache.path(), true).unwrap();
+        let cached = offline.fetch(&hello()).unwrap();
+        assert_eq!(cached.revision, "42");
+        assert_eq!(cached.path, fetched.path);
+        assert_eq!(cached.assertions, fetched.assertions);
+
+        // a corrupted cache is refused
+        fs::write(&fetched.path, b"corrupted").unwrap();
+        let error = offline.fetch(&hello()).err().unwrap();
+        assert_eq!(error.to_string(), "Cached snap hello is missing or corrupt, run `debr fetch` again");
+        let error = offline.fetch(&Snap { channel: Some(String::from("edge")), ..hello() }).err().unwrap();
+        assert_eq!(error.to_string(), "Snap hello (edge) is not cached, run `debr fetch` first");
+    }
+
+    #[test]
+    fn digest_mismatch() {
+        let cache = tempfile::tempdir().unwrap();
+        let digest = sha3_384(b"other content");
+        let error = Store::new(&store(&digest), "amd64", cache.path(), false).unwrap().fetch(&hello()).err().unwrap();
+        assert_eq!(error.to_string(), format!("sha3-384 mismatch for hello: expected {}, got {}", digest, sha3_384(SNAP)));
+        assert!(!cache.path().join(format!("{}.snap", digest)).exists());
+        assert!(!cache.path().join("hello_stable_amd64.resolved").exists());
+    }
+
+    #[test]
+    fn invalid_digests() {
+        let cache = tempfile::tempdir().unwrap();
+        for digest in ["../../escape", &"é".repeat(48)] {
+            let error = Store::new(&store(digest), "amd64", cache.path(), false).unwrap().fetch(&hello()).err().unwrap();
+            assert!(error.to_string().starts_with("Invalid sha3-384"), "{}", error);
+        }
+        assert!(hex_decode("éa").is_err());
+        assert!(hex_decode("0g").is_err());
+        assert_eq!(hex_decode("00ff").unwrap(), [0, 255]);
+    }
+}
@@ -0,0 +1,147 @@
+use std::error::Error;
+use std::net::IpAddr;
+
+use super::json_cfg::{ToolPackages, ToolSet};
+use super::s;
+
+// a single package install in the chroot
+pub struct Install {
+    pub ecosystem: &'static str,
+    pub package: String,
+    pub command: String,
+}
+
+// apt packages providing the package managers used
+pub fn apt_packages(tools: &ToolPackages) -> Vec<String> {
+    let mut packages = Vec::new();
+    if has_packages(&tools.pipx) {
+        packages.push(s("pipx"));
+    }
+    if has_packages(&tools.npm) {
+        packages.push(s("npm"));
+    }
+    if has_packages(&tools.cargo) {
+        packages.push(s("cargo"));
+    }
+    packages
+}
+
+// pipx into /opt/pipx, npm into /usr/local/lib/node_modules, cargo into /usr/local; binaries end up in /usr/local/bin
+pub fn installs(tools: &ToolPackages) -> Result<Vec<Install>, Box<dyn Error>> {
+    let mut installs = Vec::new();
+    for (ecosystem, set) in [("pipx", &tools.pipx), ("npm", &tools.npm), ("cargo", &tools.cargo)] {
+        let Some(set) = set else { continue };
+        let index = match &set.index_url {
+            Some(url) => {
+                if url.is_empty() || !is_safe(url) {
+                    return Err(format!("Invalid `indexUrl` `{}` for {} packages", url, ecosystem).into());
+                }
+                url.as_str()
+            }
+            None => "",
+        };
+
+        for spec in set.packages.iter().flatten() {
+            let (name, version) = parse_spec(ecosystem, spec)?;
+            let command = match ecosystem {
+                "pipx" => {
+                    let package = version.map_or(s(name), |v| format!("{}=={}", name, v));
+                    let index = if index.is_empty() { s("") } else { format!(" --index-url \"{}\"", index) };
+                    format!("PIPX_HOME=/opt/pipx PIPX_BIN_DIR=/usr/local/bin PIPX_MAN_DIR=/usr/local/share/man PIP_NO_CACHE_DIR=1 pipx install{} \"{}\"", index, package)
+                }
+                "npm" => {
+                    let package = version.map_or(s(name), |v| format!("{}@{}", name, v));
+                    let index = if index.is_empty() { s("") } else { format!(" --registry \"{}\"", index) };
+                    format!("npm install --global --prefix /usr/local --cache /tmp/debr-npm{} \"{}\"", index, package)
+                }
+                _ => {
+                    let version = version.map_or(s(""), |v| format!(" --version \"{}\"", v));
+                    let index = if index.is_empty() { s("") } else { format!(" --index \"{}\"", index) };
+                    format!("CARGO_HOME=/tmp/debr-cargo cargo install --locked --root /usr/local{}{} \"{}\"", index, version, name)
+                }
+            };
+            installs.push(Install { ecosystem, package: spec.clone(), command });
+        }
+    }
+    Ok(installs)
+}
+
+// ecosystems with packages to install from a registry which isn't served on this machine
+pub fn remote(tools: &ToolPackages) -> Vec<&'static str> {
+    [("pipx", &tools.pipx), ("npm", &tools.npm), ("cargo", &tools.cargo)].into_iter()
+        .filter(|(_, set)| has_packages(set) && !set.as_ref().and_then(|set| set.index_url.as_deref()).is_some_and(is_local))
+        .map(|(ecosystem, _)| ecosystem)
+        .collect()
+}
+
+// a file:// URL or one of a loopback address, the chroot shares the network of the host
+fn is_local(url: &str) -> bool {
+    let url = url.strip_prefix("sparse+").unwrap_or(url);
+    if url.starts_with("file://") {
+        return true;
+    }
+    let Some((_, rest)) = url.split_once("://") else {
+        return false;
+    };
+    let authority = rest.split('/').next().unwrap_or_default();
+    let host = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    let host = match host.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    host == "localhost" || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+fn has_packages(set: &Option<ToolSet>) -> bool {
+    set.as_ref().is_some_and(|set| set.packages.as_ref().is_some_and(|p| !p.is_empty()))
+}
+
+// `name` or `name@version`, scoped npm packages start with `@`
+fn parse_spec<'a>(ecosystem: &str, spec: &'a str) -> Result<(&'a str, Option<&'a str>), Box<dyn Error>> {
+    let (name, version) = match spec.rfind('@') {
+        Some(at) if at > 0 => (&spec[..at], Some(&spec[at + 1..])),
+        _ => (spec, None),
+    };
+    let valid_name = !name.is_empty()
+        && !name.starts_with('-')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || "-_./@[],".contains(c));
+    if !valid_name || version.is_some_and(|v| v.is_empty() || !is_safe(v)) {
+        return Err(format!("Invalid {} package `{}`, expected `name` or `name@version`", ecosystem, spec).into());
+    }
+    Ok((name, version))
+}
+
+// no whitespace or characters with a meaning inside double quotes
+fn is_safe(value: &str) -> bool {
+    !value.chars().any(|c| c.is_whitespace() || "\"$`\\".contains(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_urls() {
+        assert!(is_local("http://127.0.0.1:8080/simple/"));
+        assert!(is_local("sparse+http://localhost:3000/index/"));
+        assert!(is_local("http://user@[::1]:4873/"));
+        assert!(is_local("file:///srv/crates-index"));
+        assert!(!is_local("https://npm.example.org/"));
+        assert!(!is_local("http://127.0.0.1.example.org/"));
+        assert!(!is_local("127.0.0.1"));
+    }
+
+    #[test]
+    fn remote_ecosystems() {
+        let set = |index_url: Option<&str>, packages: &[&str]| Some(ToolSet {
+            index_url: index_url.map(s),
+            packages: Some(packages.iter().map(|p| s(p)).collect()),
+        });
+        let tools = ToolPackages {
+            pipx: set(None, &["black"]),
+            npm: set(Some("http://127.0.0.1:4873/"), &["typescript"]),
+            cargo: set(None, &[]),
+        };
+        assert_eq!(remote(&tools), ["pipx"]);
+    }
+}
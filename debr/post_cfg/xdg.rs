@@ -0,0 +1,202 @@
+use std::error::Error;
+use std::fs::{create_dir_all, set_permissions, write};
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use ordermap::{OrderMap, OrderSet};
+
+use super::json_cfg::DesktopEntry;
+
+// https://specifications.freedesktop.org/desktop-entry-spec/latest/exec-variables.html
+const FIELD_CODES: &str = "fFuUick%";
+const DEPRECATED_FIELD_CODES: &str = "dDnNvm";
+
+// desktop entries, autostart entries and mime defaults of the image
+#[derive(Default)]
+pub struct Xdg {
+    applications: OrderMap<String, String>,
+    autostart: OrderMap<String, String>,
+    mime_defaults: OrderMap<String, String>,
+    binaries: OrderSet<String>,
+}
+
+impl Xdg {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.applications.is_empty() && self.autostart.is_empty() && self.mime_defaults.is_empty()
+    }
+
+    // launcher in /usr/share/applications
+    pub fn add_application(&mut self, entry: &DesktopEntry) -> Result<(), Box<dyn Error>> {
+        let (id, content) = self.render(entry)?;
+        if self.applications.insert(id.clone(), content).is_some() {
+            return Err(format!("Desktop entry `{}` is defined multiple times", id).into());
+        }
+        Ok(())
+    }
+
+    // entry in /etc/xdg/autostart
+    pub fn add_autostart(&mut self, entry: &DesktopEntry) -> Result<(), Box<dyn Error>> {
+        let (id, content) = self.render(entry)?;
+        if self.autostart.insert(id.clone(), content).is_some() {
+            return Err(format!("Autostart entry `{}` is defined multiple times", id).into());
+        }
+        Ok(())
+    }
+
+    // whether a custom autostart entry has this desktop file id
+    pub fn has_autostart(&self, id: &str) -> bool {
+        desktop_id(id).is_ok_and(|id| self.autostart.contains_key(&id))
+    }
+
+    pub fn add_mime_default(&mut self, mime_type: &str, app: &str) -> Result<(), Box<dyn Error>> {
+        validate_mime_type(mime_type)?;
+        let app = desktop_id(app)?;
+        self.mime_defaults.insert(mime_type.to_string(), app);
+        Ok(())
+    }
+
+    // binaries referenced by `Exec`, to be checked in the chroot
+    pub fn binaries(&self) -> &OrderSet<String> {
+        &self.binaries
+    }
+
+    pub fn place(&self, includes_dir: &Path) -> io::Result<()> {
+        let places = [
+            ("usr/share/applications", &self.applications),
+            ("etc/xdg/autostart", &self.autostart),
+        ];
+        for (dir, entries) in places {
+            if entries.is_empty() {
+                continue;
+            }
+            let dir = includes_dir.join(dir);
+            create_dir_all(&dir)?;
+            for (id, content) in entries {
+                let path = dir.join(id);
+                write(&path, content)?;
+                set_permissions(&path, PermissionsExt::from_mode(0o644))?;
+            }
+        }
+
+        if !self.mime_defaults.is_empty() {
+            let mut content = String::from("[Default Applications]\n");
+            for (mime_type, app) in &self.mime_defaults {
+                content.push_str(&format!("{}={}\n", mime_type, app));
+            }
+            let path = includes_dir.join("etc/xdg/mimeapps.list");
+            create_dir_all(path.parent().unwrap())?;
+            write(&path, content)?;
+            set_permissions(&path, PermissionsExt::from_mode(0o644))?;
+        }
+        Ok(())
+    }
+
+    fn render(&mut self, entry: &DesktopEntry) -> Result<(String, String), Box<dyn Error>> {
+        let id = desktop_id(&entry.id)?;
+        let context = |e: Box<dyn Error>| -> Box<dyn Error> { format!("Invalid desktop entry `{}`: {}", id, e).into() };
+
+        let entry_type = entry.entry_type.clone().unwrap_or("Application".to_string());
+        let mut content = String::from("[Desktop Entry]\n");
+        content.push_str(&format!("Type={}\n", entry_type));
+        content.push_str(&format!("Name={}\n", string_value(&entry.name).map_err(context)?));
+        if let Some(comment) = &entry.comment {
+            content.push_str(&format!("Comment={}\n", string_value(comment).map_err(context)?));
+        }
+        if let Some(icon) = &entry.icon {
+            content.push_str(&format!("Icon={}\n", string_value(icon).map_err(context)?));
+        }
+
+        match entry_type.as_str() {
+            "Application" => {
+                let exec = entry.exec.as_ref().ok_or_else(|| context("`exec` is required for type `Application`".into()))?;
+                let binary = validate_exec(exec).map_err(context)?;
+                content.push_str(&format!("Exec={}\n", exec));
+                content.push_str(&format!("Terminal={}\n", entry.terminal.unwrap_or(false)));
+                self.binaries.insert(binary);
+            }
+            "Link" => {
+                let url = entry.url.as_ref().ok_or_else(|| context("`url` is required for type `Link`".into()))?;
+                content.push_str(&format!("URL={}\n", string_value(url).map_err(context)?));
+            }
+            other => return Err(context(format!("unknown type `{}` (Allowed values: `Application`, `Link`)", other).into())),
+        }
+
+        if let Some(categories) = &entry.categories {
+            for category in categories {
+                if category.is_empty() || !category.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+                    return Err(context(format!("invalid category `{}`", category).into()));
+                }
+            }
+            content.push_str(&format!("Categories={};\n", categories.join(";")));
+        }
+        if let Some(mime_types) = &entry.mime_types {
+            for mime_type in mime_types {
+                validate_mime_type(mime_type).map_err(context)?;
+            }
+            content.push_str(&format!("MimeType={};\n", mime_types.join(";")));
+        }
+        if entry.no_display.unwrap_or(false) {
+            content.push_str("NoDisplay=true\n");
+        }
+        Ok((id, content))
+    }
+}
+
+// normalizes to a desktop file id ending with `.desktop`
+fn desktop_id(id: &str) -> Result<String, Box<dyn Error>> {
+    let name = id.strip_suffix(".desktop").unwrap_or(id);
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c)) {
+        return Err(format!("Invalid desktop file id `{}`, allowed are ASCII letters, digits, `-`, `_` and `.`", id).into());
+    }
+    Ok(format!("{}.desktop", name))
+}
+
+fn string_value(value: &str) -> Result<&str, Box<dyn Error>> {
+    if value.is_empty() || value.chars().any(|c| c.is_control()) {
+        return Err(format!("value `{}` is empty or contains control characters", value.escape_default()).into());
+    }
+    Ok(value)
+}
+
+fn validate_mime_type(mime_type: &str) -> Result<(), Box<dyn Error>> {
+    let valid = match mime_type.split_once('/') {
+        Some((kind, subtype)) => {
+            let valid_part = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || "!#$&^_.+-".contains(c));
+            valid_part(kind) && valid_part(subtype)
+        }
+        None => false,
+    };
+    if !valid {
+        return Err(format!("invalid mime type `{}`", mime_type).into());
+    }
+    Ok(())
+}
+
+// validates the field codes and returns the executable
+fn validate_exec(exec: &str) -> Result<String, Box<dyn Error>> {
+    string_value(exec)?;
+    let mut chars = exec.chars();
+    while let Some(c) = chars.next() {
+        if c == '%' {
+            match chars.next() {
+                Some(code) if FIELD_CODES.contains(code) || DEPRECATED_FIELD_CODES.contains(code) => {}
+                Some(code) => return Err(format!("invalid field code `%{}` in `Exec`", code).into()),
+                None => return Err("`Exec` ends with an incomplete field code".into()),
+            }
+        }
+    }
+
+    let binary = if let Some(quoted) = exec.strip_prefix('"') {
+        quoted.split('"').next().unwrap_or_default()
+    } else {
+        exec.split_whitespace().next().unwrap_or_default()
+    };
+    if binary.is_empty() || binary.contains('=') {
+        return Err(format!("`Exec` has to start with the program, got `{}`", exec).into());
+    }
+    Ok(binary.to_string())
+}
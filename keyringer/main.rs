@@ -211,9 +211,9 @@ fn load_cfg(config_path: &str) -> Result<HashMap<String, String>, Box<dyn Error>
         Err(e) => {
             let error_msg = format!("Config file at {} does not contain valid JSON objects.", CONFIG_FILE);
             if e.to_string().contains("trailing characters") {
-                return Err(Box::new(io::Error::new(ErrorKind::InvalidData, error_msg)));
+                Err(Box::new(io::Error::new(ErrorKind::InvalidData, error_msg)))
             } else {
-                return Err(e.into());
+                Err(e.into())
             }
         }
     }
@@ -21,6 +21,7 @@ pub fn update_keyrings(keyrings: HashMap<String, String>) -> Result<(), Box<dyn
         info(&format!("Created directory: {}", keyrings_dir.display()));
     }
 
+    // proxies are read from the environment, see keyringer.service
     let client = ClientBuilder::new()
         .https_only(true)
         .build()?;
@@ -116,7 +117,7 @@ pub fn dearmor_to(input: &[u8], output_path: &Path) -> Result<(), Box<dyn Error>
         .write(true)
         .create(do_create)
         .truncate(true)
-        .open(&output_path)?;
+        .open(output_path)?;
     file.write_all(&output.stdout)?;
 
     info(&format!("Updated keyring: {}", output_path.display()));